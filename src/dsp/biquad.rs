@@ -0,0 +1,281 @@
+use std::f64::consts;
+
+/// Response of a single biquad section, from the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    /// Band pass with 0dB peak gain.
+    BandPass,
+    Notch,
+    AllPass,
+    /// Peaking EQ, gain in dB.
+    Peak(f64),
+    /// Low shelf, gain in dB.
+    LowShelf(f64),
+    /// High shelf, gain in dB.
+    HighShelf(f64),
+    /// First order low pass, Q is ignored.
+    LowPass1,
+    /// First order high pass, Q is ignored.
+    HighPass1,
+}
+
+/// Q of a second order Butterworth section.
+pub const BUTTERWORTH_Q: f64 = consts::FRAC_1_SQRT_2;
+
+/// Biquad filter in transposed direct form II.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn new(kind: FilterType, frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let mut bq = Biquad { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0, z1: 0.0, z2: 0.0 };
+        bq.set(kind, frequency, q, sample_rate);
+        bq
+    }
+
+    /// Biquad with coefficients normalized so a0 is 1, for responses the cookbook doesn't cover.
+    pub fn from_coefficients(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad { b0, b1, b2, a1, a2, z1: 0.0, z2: 0.0 }
+    }
+
+    /// Recalculate coefficients, keeping the filter state so parameters can change while running.
+    pub fn set(&mut self, kind: FilterType, frequency: f64, q: f64, sample_rate: f64) {
+        let w0 = 2.0 * consts::PI * frequency / sample_rate;
+        let (sin_w0, cos_w0) = (w0.sin(), w0.cos());
+        let alpha = sin_w0 / (2.0 * q);
+        let gain_a = |db: f64| 10.0f64.powf(db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterType::LowPass => {
+                let b1 = 1.0 - cos_w0;
+                (b1 / 2.0, b1, b1 / 2.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+            }
+            FilterType::HighPass => {
+                let b1 = -(1.0 + cos_w0);
+                (-b1 / 2.0, b1, -b1 / 2.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+            }
+            FilterType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha),
+            FilterType::Notch => (1.0, -2.0 * cos_w0, 1.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha),
+            FilterType::AllPass => {
+                (1.0 - alpha, -2.0 * cos_w0, 1.0 + alpha, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+            }
+            FilterType::Peak(db) => {
+                let a = gain_a(db);
+                (1.0 + alpha * a, -2.0 * cos_w0, 1.0 - alpha * a,
+                 1.0 + alpha / a, -2.0 * cos_w0, 1.0 - alpha / a)
+            }
+            FilterType::LowShelf(db) => {
+                let a = gain_a(db);
+                let sq = 2.0 * a.sqrt() * alpha;
+                (a * ((a + 1.0) - (a - 1.0) * cos_w0 + sq),
+                 2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                 a * ((a + 1.0) - (a - 1.0) * cos_w0 - sq),
+                 (a + 1.0) + (a - 1.0) * cos_w0 + sq,
+                 -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                 (a + 1.0) + (a - 1.0) * cos_w0 - sq)
+            }
+            FilterType::HighShelf(db) => {
+                let a = gain_a(db);
+                let sq = 2.0 * a.sqrt() * alpha;
+                (a * ((a + 1.0) + (a - 1.0) * cos_w0 + sq),
+                 -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                 a * ((a + 1.0) + (a - 1.0) * cos_w0 - sq),
+                 (a + 1.0) - (a - 1.0) * cos_w0 + sq,
+                 2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                 (a + 1.0) - (a - 1.0) * cos_w0 - sq)
+            }
+            FilterType::LowPass1 => {
+                let k = (w0 / 2.0).tan();
+                (k, k, 0.0, k + 1.0, k - 1.0, 0.0)
+            }
+            FilterType::HighPass1 => {
+                let k = (w0 / 2.0).tan();
+                (1.0, -1.0, 0.0, k + 1.0, k - 1.0, 0.0)
+            }
+        };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    /// Filter one sample.
+    pub fn process(&mut self, x: f32) -> f32 {
+        let x = x as f64;
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y as f32
+    }
+
+    /// Filter samples in place.
+    pub fn process_buffer(&mut self, samples: &mut [f32]) {
+        for s in samples.iter_mut() {
+            *s = self.process(*s);
+        }
+    }
+}
+
+/// Biquad sections run in series.
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    stages: Vec<Biquad>,
+}
+
+impl FilterChain {
+    pub fn new() -> FilterChain {
+        FilterChain { stages: Vec::new() }
+    }
+
+    pub fn push(&mut self, stage: Biquad) {
+        self.stages.push(stage);
+    }
+
+    /// Append all stages of another chain.
+    pub fn extend(&mut self, other: FilterChain) {
+        self.stages.extend(other.stages);
+    }
+
+    pub fn process_buffer(&mut self, samples: &mut [f32]) {
+        for bq in self.stages.iter_mut() {
+            bq.process_buffer(samples);
+        }
+    }
+}
+
+/// Butterworth low or high pass of any order, as cascaded biquads plus a first order section
+/// for odd orders.
+pub fn butterworth(high_pass: bool, order: usize, frequency: f64, sample_rate: f64) -> FilterChain {
+    let mut chain = FilterChain::new();
+    let n = order as f64;
+    for k in 1..(order / 2 + 1) {
+        let q = 1.0 / (2.0 * ((2.0 * k as f64 - 1.0) * consts::PI / (2.0 * n)).sin());
+        let kind = if high_pass { FilterType::HighPass } else { FilterType::LowPass };
+        chain.push(Biquad::new(kind, frequency, q, sample_rate));
+    }
+    if !order.is_multiple_of(2) {
+        let kind = if high_pass { FilterType::HighPass1 } else { FilterType::LowPass1 };
+        chain.push(Biquad::new(kind, frequency, BUTTERWORTH_Q, sample_rate));
+    }
+    chain
+}
+
+/// Linkwitz-Riley crossover filter, two Butterworth filters of half the order in series.
+///
+/// Order must be even, LR low and high pass of the same order sum flat.
+pub fn linkwitz_riley(high_pass: bool, order: usize, frequency: f64, sample_rate: f64) -> FilterChain {
+    assert!(order.is_multiple_of(2), "Linkwitz-Riley order must be even.");
+    let mut chain = butterworth(high_pass, order / 2, frequency, sample_rate);
+    chain.extend(butterworth(high_pass, order / 2, frequency, sample_rate));
+    chain
+}
+
+/// Parse a comma separated filter chain, `lp:1000:0.7,peak:3000:1.4:-6,bwhp:80:4`.
+///
+/// Each filter is `TYPE:FREQ`, followed by Q and then gain in dB for peak and shelves. The
+/// Butterworth (`bwlp`, `bwhp`) and Linkwitz-Riley (`lrlp`, `lrhp`) types take an order in place
+/// of Q, defaulting to 4.
+pub fn parse_filter_chain(spec: &str, sample_rate: f64) -> Result<FilterChain, String> {
+    let mut chain = FilterChain::new();
+
+    for entry in spec.split(',') {
+        let fields: Vec<&str> = entry.trim().split(':').collect();
+        let num = |i: usize, default: Option<f64>| -> Result<f64, String> {
+            match fields.get(i) {
+                Some(f) => f.parse().map_err(|_| format!("Bad number '{}' in '{}'", f, entry)),
+                None => default.ok_or_else(|| format!("Missing parameter in '{}'", entry)),
+            }
+        };
+        let freq = num(1, None)?;
+        if freq <= 0.0 || freq >= sample_rate / 2.0 {
+            return Err(format!("Frequency out of range in '{}'", entry));
+        }
+        let q = num(2, Some(BUTTERWORTH_Q))?;
+        // Whole number order of at least lowest.
+        let order = |lowest: f64| -> Result<usize, String> {
+            match num(2, Some(4.0))? {
+                o if o >= lowest && o.fract() == 0.0 => Ok(o as usize),
+                _ => Err(format!("Order must be a whole number from {} in '{}'", lowest, entry)),
+            }
+        };
+
+        let kind = match fields[0] {
+            "lp" => FilterType::LowPass,
+            "hp" => FilterType::HighPass,
+            "bp" => FilterType::BandPass,
+            "notch" => FilterType::Notch,
+            "ap" | "allpass" => FilterType::AllPass,
+            "peak" => FilterType::Peak(num(3, None)?),
+            "lowshelf" => FilterType::LowShelf(num(3, None)?),
+            "highshelf" => FilterType::HighShelf(num(3, None)?),
+            "bwlp" | "bwhp" => {
+                chain.extend(butterworth(fields[0] == "bwhp", order(1.0)?, freq, sample_rate));
+                continue;
+            }
+            "lrlp" | "lrhp" => {
+                let order = order(2.0)?;
+                if !order.is_multiple_of(2) {
+                    return Err(format!("Linkwitz-Riley order must be even in '{}'", entry));
+                }
+                chain.extend(linkwitz_riley(fields[0] == "lrhp", order, freq, sample_rate));
+                continue;
+            }
+            t => return Err(format!("Unknown filter type '{}'", t)),
+        };
+        if q <= 0.0 || !q.is_finite() {
+            return Err(format!("Q out of range in '{}'", entry));
+        }
+        chain.push(Biquad::new(kind, freq, q, sample_rate));
+    }
+
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synth::oscillator::SineOscillator;
+
+    // Steady state peak level of a filtered sine.
+    fn gain_at(chain: &mut FilterChain, frequency: f64) -> f32 {
+        let mut osc = SineOscillator::new(48000.0);
+        let mut samples: Vec<f32> = (0..48000).map(|_| osc.tick(frequency)).collect();
+        chain.process_buffer(&mut samples);
+        samples[24000..].iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn butterworth_cutoff_is_minus_3db() {
+        for order in 1..6 {
+            let mut lp = butterworth(false, order, 1000.0, 48000.0);
+            assert!((gain_at(&mut lp, 1000.0) - consts::FRAC_1_SQRT_2 as f32).abs() < 0.01);
+        }
+        let mut hp = butterworth(true, 4, 1000.0, 48000.0);
+        assert!(gain_at(&mut hp, 100.0) < 0.001);
+    }
+
+    #[test]
+    fn peak_gain() {
+        let mut chain = parse_filter_chain("peak:2000:1.0:6", 48000.0).unwrap();
+        assert!((gain_at(&mut chain, 2000.0) - 1.995).abs() < 0.01);
+        assert!(parse_filter_chain("lrlp:1000:3", 48000.0).is_err());
+        for spec in ["bwlp:1000:0", "bwhp:1000:-2", "bwlp:1000:2.5", "lrlp:1000:0", "lrhp:1000:-4",
+                     "lp:1000:0", "peak:2000:-1:6", "hp:1000:inf"].iter() {
+            assert!(parse_filter_chain(spec, 48000.0).is_err(), "{}", spec);
+        }
+        assert_eq!(parse_filter_chain("bwlp:1000:1,lrhp:80:2", 48000.0).map(|c| c.stages.len()), Ok(3));
+        assert!(parse_filter_chain("xx:1000", 48000.0).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::f64::consts;
+
+/// Feedback of a delay when none is given.
+pub const DEFAULT_DELAY_FEEDBACK: f32 = 0.3;
+/// Mix of a delay when none is given.
+pub const DEFAULT_DELAY_MIX: f32 = 0.5;
+
+/// Effect run over a signal a sample at a time, keeping its own state between blocks.
+pub trait Effect {
+    /// Process one sample.
+    fn process(&mut self, x: f32) -> f32;
+
+    /// Process samples in place.
+    fn process_buffer(&mut self, samples: &mut [f32]) {
+        for s in samples.iter_mut() {
+            *s = self.process(*s);
+        }
+    }
+}
+
+/// Length in seconds of a note value like 1/8, dotted 1/8d or triplet 1/8t, at bpm quarter
+/// notes a minute.
+pub fn note_length(text: &str, bpm: f64) -> Option<f64> {
+    let (fraction, scale) = if let Some(f) = text.strip_suffix('d') {
+        (f, 1.5)
+    } else if let Some(f) = text.strip_suffix('t') {
+        (f, 2.0 / 3.0)
+    } else {
+        (text, 1.0)
+    };
+    let mut parts = fraction.splitn(2, '/');
+    let num: f64 = parts.next()?.trim().parse().ok()?;
+    let den: f64 = parts.next()?.trim().parse().ok()?;
+    if num <= 0.0 || den <= 0.0 || bpm <= 0.0 {
+        return None;
+    }
+    // A whole note is four beats.
+    Some(num / den * 4.0 * 60.0 / bpm * scale)
+}
+
+/// Delay time given in seconds, or as a note value synced to bpm.
+pub fn parse_delay_time(text: &str, bpm: Option<f64>) -> Result<f64, String> {
+    if !text.contains('/') {
+        return match text.trim().parse::<f64>() {
+            Ok(t) if t > 0.0 => Ok(t),
+            _ => Err(format!("bad delay time '{}'", text)),
+        };
+    }
+    let bpm = bpm.ok_or_else(|| format!("no tempo for the note length '{}'", text))?;
+    note_length(text, bpm).ok_or_else(|| format!("bad note length '{}'", text))
+}
+
+/// Echo from a delay line with its output fed back into its input.
+#[derive(Debug, Clone)]
+pub struct Delay {
+    line: Vec<f32>,
+    pos: usize,
+    feedback: f32,
+    // Level of the delayed signal mixed with the dry.
+    mix: f32,
+}
+
+impl Delay {
+    /// Delay of time seconds, feedback and mix from 0.0 to 1.0.
+    pub fn new(time: f64, feedback: f32, mix: f32, sample_rate: f64) -> Delay {
+        let len = ((time * sample_rate).round() as usize).max(1);
+        Delay { line: vec![0.0; len], pos: 0, feedback: feedback.clamp(0.0, 0.99), mix: mix.clamp(0.0, 1.0) }
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.line[self.pos];
+        self.line[self.pos] = x + delayed * self.feedback;
+        self.pos += 1;
+        if self.pos >= self.line.len() {
+            self.pos = 0;
+        }
+        x * (1.0 - self.mix) + delayed * self.mix
+    }
+}
+
+/// Delay line read between samples, for delay times that move.
+#[derive(Debug, Clone)]
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    // Where the next sample is written.
+    pos: usize,
+}
+
+impl DelayLine {
+    /// Line able to delay by up to max_delay samples.
+    pub fn new(max_delay: usize) -> DelayLine {
+        DelayLine { buffer: vec![0.0; max_delay + 2], pos: 0 }
+    }
+
+    /// Sample from delay samples before the next to be written, 1.0 being the last written,
+    /// interpolated linearly between samples.
+    pub fn read(&self, delay: f64) -> f32 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1.0, (len - 1) as f64);
+        let whole = delay.floor() as usize;
+        let frac = (delay - whole as f64) as f32;
+        let a = self.buffer[(self.pos + len - whole) % len];
+        let b = self.buffer[(self.pos + len - whole - 1) % len];
+        a + (b - a) * frac
+    }
+
+    /// Push a sample into the line.
+    pub fn write(&mut self, x: f32) {
+        self.buffer[self.pos] = x;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+}
+
+// Shortest delay and sweep of the chorus and flanger in seconds.
+const CHORUS_DELAY: f64 = 0.010;
+const CHORUS_SWEEP: f64 = 0.020;
+const FLANGER_DELAY: f64 = 0.0001;
+const FLANGER_SWEEP: f64 = 0.005;
+
+/// Depth of a chorus when none is given.
+pub const DEFAULT_CHORUS_DEPTH: f64 = 0.5;
+/// Mix of a chorus when none is given.
+pub const DEFAULT_CHORUS_MIX: f32 = 0.5;
+/// Depth of a flanger when none is given.
+pub const DEFAULT_FLANGER_DEPTH: f64 = 0.5;
+/// Feedback of a flanger when none is given.
+pub const DEFAULT_FLANGER_FEEDBACK: f32 = 0.5;
+/// Mix of a flanger when none is given.
+pub const DEFAULT_FLANGER_MIX: f32 = 0.5;
+
+/// Copy of a signal through a delay swept by a sine LFO, mixed back with the dry signal. A
+/// chorus sweeps a longer delay to thicken the sound, and a flanger a shorter one fed back to
+/// make moving notches.
+#[derive(Debug, Clone)]
+pub struct ModulatedDelay {
+    line: DelayLine,
+    // Shortest delay and the sweep above it, in samples.
+    min_delay: f64,
+    sweep: f64,
+    phase: f64,
+    phase_inc: f64,
+    feedback: f32,
+    mix: f32,
+}
+
+impl ModulatedDelay {
+    /// Sweep a delay of min_delay to min_delay + sweep seconds rate times a second.
+    pub fn new(rate: f64, min_delay: f64, sweep: f64, feedback: f32, mix: f32, sample_rate: f64) -> ModulatedDelay {
+        let min_delay = (min_delay * sample_rate).max(1.0);
+        let sweep = (sweep * sample_rate).max(0.0);
+        ModulatedDelay {
+            line: DelayLine::new((min_delay + sweep).ceil() as usize + 1),
+            min_delay,
+            sweep,
+            phase: 0.0,
+            phase_inc: 2.0 * consts::PI * rate / sample_rate,
+            feedback: feedback.clamp(-0.95, 0.95),
+            mix: mix.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Chorus at rate Hz, depth and mix from 0.0 to 1.0.
+    pub fn chorus(rate: f64, depth: f64, mix: f32, sample_rate: f64) -> ModulatedDelay {
+        ModulatedDelay::new(rate, CHORUS_DELAY, CHORUS_SWEEP * depth.clamp(0.0, 1.0), 0.0, mix, sample_rate)
+    }
+
+    /// Flanger at rate Hz, depth and mix from 0.0 to 1.0 and feedback from -1.0 to 1.0.
+    pub fn flanger(rate: f64, depth: f64, feedback: f32, mix: f32, sample_rate: f64) -> ModulatedDelay {
+        ModulatedDelay::new(rate, FLANGER_DELAY, FLANGER_SWEEP * depth.clamp(0.0, 1.0), feedback, mix,
+                            sample_rate)
+    }
+}
+
+impl Effect for ModulatedDelay {
+    fn process(&mut self, x: f32) -> f32 {
+        // Starts at the shortest delay.
+        let delay = self.min_delay + self.sweep * 0.5 * (1.0 - self.phase.cos());
+        self.phase += self.phase_inc;
+        if self.phase >= 2.0 * consts::PI {
+            self.phase -= 2.0 * consts::PI;
+        }
+        let delayed = self.line.read(delay);
+        self.line.write(x + delayed * self.feedback);
+        x * (1.0 - self.mix) + delayed * self.mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_whole_and_fractional_samples() {
+        assert_eq!(parse_delay_time("0.25", None), Ok(0.25));
+        assert_eq!(parse_delay_time("1/8", Some(120.0)), Ok(0.25));
+        assert_eq!(parse_delay_time("1/8d", Some(120.0)), Ok(0.375));
+        assert!((parse_delay_time("1/4t", Some(120.0)).unwrap() - 1.0 / 3.0).abs() < 1.0e-12);
+        assert!(parse_delay_time("1/8", None).is_err());
+
+        // An impulse echoes after the delay time, each echo feedback times the last.
+        let mut delay = Delay::new(0.01, 0.5, 1.0, 1000.0);
+        let mut samples = vec![0.0f32; 40];
+        samples[0] = 1.0;
+        delay.process_buffer(&mut samples);
+        assert_eq!((samples[10], samples[20], samples[30]), (1.0, 0.5, 0.25));
+        assert_eq!(samples.iter().filter(|s| **s != 0.0).count(), 3);
+
+        let mut line = DelayLine::new(8);
+        for x in 0..8 {
+            line.write(x as f32);
+        }
+        assert_eq!(line.read(1.0), 7.0);
+        assert_eq!(line.read(2.5), 5.5);
+
+        // With no sweep the flanger is a comb, a sine at its notch cancelled.
+        let sample_rate = 48000.0;
+        let mut flanger = ModulatedDelay::new(0.0, 0.001, 0.0, 0.0, 0.5, sample_rate);
+        let notch: Vec<f32> = (0..4800).map(|i| (i as f64 * 2.0 * consts::PI * 500.0 / sample_rate).sin() as f32)
+                                       .map(|x| flanger.process(x)).collect();
+        assert!(notch[100..].iter().all(|s| s.abs() < 1.0e-3));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::f64::consts;
+use std::iter;
+
+// True peaks are found by interpolating four points per sample, with a Hann windowed sinc
+// reaching this many samples either side.
+const OVERSAMPLING: usize = 4;
+const INTERPOLATION_REACH: usize = 6;
+
+/// Lookahead of the limiter when none is given, in seconds.
+pub const DEFAULT_LOOKAHEAD: f64 = 0.005;
+/// Time the limiter takes to recover after a peak, in seconds.
+pub const DEFAULT_LIMITER_RELEASE: f64 = 0.1;
+
+// Soft clipping starts this far below the ceiling, as a fraction of it.
+const SOFT_KNEE: f32 = 0.7;
+
+// Interpolation taps for each fractional position, from INTERPOLATION_REACH - 1 samples back
+// to INTERPOLATION_REACH ahead.
+fn interpolation_taps() -> Vec<Vec<f64>> {
+    let reach = INTERPOLATION_REACH as f64;
+    (0..OVERSAMPLING).map(|p| {
+        let frac = p as f64 / OVERSAMPLING as f64;
+        let taps: Vec<f64> = (0..2 * INTERPOLATION_REACH).map(|j| {
+            let u = frac - (j as f64 - reach + 1.0);
+            let sinc = if u == 0.0 { 1.0 } else { (consts::PI * u).sin() / (consts::PI * u) };
+            let window = if u.abs() < reach { 0.5 * (1.0 + (consts::PI * u / reach).cos()) } else { 0.0 };
+            sinc * window
+        }).collect();
+        // Scaled to unity gain at DC, or slow signals would read slightly over their peaks.
+        let sum: f64 = taps.iter().sum();
+        taps.iter().map(|h| h / sum).collect()
+    }).collect()
+}
+
+/// Largest magnitude of the signal between each sample and the next, found by oversampling
+/// four times as ITU-R BS.1770 describes for true peak meters.
+pub fn true_peak_envelope(samples: &[f32]) -> Vec<f32> {
+    let taps = interpolation_taps();
+    let at = |k: isize| if k >= 0 && (k as usize) < samples.len() { samples[k as usize] as f64 } else { 0.0 };
+    (0..samples.len()).map(|i| {
+        let first = i as isize - INTERPOLATION_REACH as isize + 1;
+        taps.iter().map(|phase| {
+            let value: f64 = phase.iter().enumerate().map(|(j, h)| h * at(first + j as isize)).sum();
+            value.abs() as f32
+        }).fold(samples[i].abs(), f32::max)
+    }).collect()
+}
+
+/// Samples beyond full scale in a signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overs {
+    /// Samples over full scale.
+    pub samples: usize,
+    /// Samples within full scale followed by an over before the next sample.
+    pub between_samples: usize,
+    /// True peak in dBTP.
+    pub true_peak: f64,
+}
+
+/// Counts the overs of a signal given a block at a time, as find_overs() does for a whole one.
+pub struct OverMeter {
+    taps: Vec<Vec<f64>>,
+    // The interpolation window, the sample being measured INTERPOLATION_REACH - 1 from the front.
+    window: VecDeque<f32>,
+    samples: usize,
+    between_samples: usize,
+    peak: f32,
+}
+
+impl OverMeter {
+    pub fn new() -> OverMeter {
+        OverMeter {
+            taps: interpolation_taps(),
+            window: iter::repeat_n(0.0, INTERPOLATION_REACH - 1).collect(),
+            samples: 0,
+            between_samples: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Measure the next samples.
+    pub fn process(&mut self, samples: &[f32]) {
+        for s in samples {
+            self.push(*s);
+        }
+    }
+
+    // Each sample is measured once the samples after it that it's interpolated from arrive.
+    fn push(&mut self, sample: f32) {
+        self.window.push_back(sample);
+        if self.window.len() < 2 * INTERPOLATION_REACH {
+            return;
+        }
+        let current = self.window[INTERPOLATION_REACH - 1];
+        let window = &self.window;
+        let envelope = self.taps.iter().map(|phase| {
+            let value: f64 = phase.iter().zip(window.iter()).map(|(h, x)| h * *x as f64).sum();
+            value.abs() as f32
+        }).fold(current.abs(), f32::max);
+        if current.abs() > 1.0 {
+            self.samples += 1;
+        } else if envelope > 1.0 {
+            self.between_samples += 1;
+        }
+        self.peak = self.peak.max(envelope);
+        self.window.pop_front();
+    }
+
+    /// Measure the last samples against silence after the end, and give the count.
+    pub fn finish(mut self) -> Overs {
+        for _ in 0..INTERPOLATION_REACH {
+            self.push(0.0);
+        }
+        Overs { samples: self.samples, between_samples: self.between_samples,
+                true_peak: 20.0 * (self.peak as f64).log10() }
+    }
+}
+
+/// Count the overs in a channel.
+pub fn find_overs(samples: &[f32]) -> Overs {
+    let mut meter = OverMeter::new();
+    meter.process(samples);
+    meter.finish()
+}
+
+/// Brickwall limit the channels together so their true peak stays under ceiling, a linear
+/// level.
+///
+/// The gain starts falling lookahead seconds before each peak, so it never has to jump, and
+/// recovers over release seconds.
+pub fn limit(channels: &mut [&mut [f32]], ceiling: f32, lookahead: f64, release: f64, sample_rate: f64) {
+    let len = channels.iter().map(|c| c.len()).max().unwrap_or(0);
+    let ahead = ((lookahead * sample_rate).round() as usize).max(1);
+
+    // Gain each sample needs on its own, from the loudest channel.
+    let mut needed: Vec<f32> = vec![1.0; len];
+    for channel in channels.iter() {
+        for (n, e) in needed.iter_mut().zip(true_peak_envelope(channel)) {
+            *n = n.min(if e > ceiling { ceiling / e } else { 1.0 });
+        }
+    }
+
+    // Hold the lowest gain needed over the lookahead, then let it recover at the release rate.
+    let recover = 1.0 - (-1.0 / (release * sample_rate)).exp() as f32;
+    let mut held: Vec<f32> = Vec::with_capacity(len);
+    let mut gain = 1.0f32;
+    for i in 0..len {
+        let lowest = needed[i..(i + ahead).min(len)].iter().fold(1.0f32, |m, g| m.min(*g));
+        gain = lowest.min(gain + (1.0 - gain) * recover);
+        held.push(gain);
+    }
+
+    // Averaging over the lookahead ramps the gain down. Every value averaged at a peak was held
+    // from a window covering that peak, so the average can't rise above what the peak needs.
+    let mut sum = 0.0f64;
+    for i in 0..len {
+        sum += held[i] as f64;
+        if i >= ahead {
+            sum -= held[i - ahead] as f64;
+        }
+        // Before the first full window the missing values count as unity gain.
+        let g = ((sum + ahead.saturating_sub(i + 1) as f64) / ahead as f64) as f32;
+        for channel in channels.iter_mut() {
+            if let Some(s) = channel.get_mut(i) {
+                *s *= g;
+            }
+        }
+    }
+}
+
+/// Lookahead limiter for a signal given a sample at a time, holding sample peaks under
+/// ceiling, a linear level, the way limit() does true peaks.
+///
+/// The output is delayed by the lookahead, less a sample.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    ceiling: f32,
+    recover: f32,
+    gain: f32,
+    // Samples waiting to go out and the gain each of them needs, oldest first.
+    delayed: VecDeque<f32>,
+    needed: VecDeque<f32>,
+    // The gains held over the last lookahead and their sum, for the moving average.
+    held: VecDeque<f32>,
+    sum: f64,
+}
+
+impl Limiter {
+    pub fn new(ceiling: f32, lookahead: f64, release: f64, sample_rate: f64) -> Limiter {
+        let ahead = ((lookahead * sample_rate).round() as usize).max(1);
+        // Starting from silence makes the first samples out wait for the lookahead to fill.
+        Limiter {
+            ceiling,
+            recover: 1.0 - (-1.0 / (release * sample_rate)).exp() as f32,
+            gain: 1.0,
+            delayed: iter::repeat_n(0.0, ahead - 1).collect(),
+            needed: iter::repeat_n(1.0, ahead - 1).collect(),
+            held: iter::repeat_n(1.0, ahead).collect(),
+            sum: ahead as f64,
+        }
+    }
+
+    /// Take the next sample in and give the next out.
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.delayed.push_back(x);
+        self.needed.push_back(if x.abs() > self.ceiling { self.ceiling / x.abs() } else { 1.0 });
+
+        let lowest = self.needed.iter().fold(1.0f32, |m, g| m.min(*g));
+        self.gain = lowest.min(self.gain + (1.0 - self.gain) * self.recover);
+        self.held.push_back(self.gain);
+        self.sum += self.gain as f64 - self.held.pop_front().unwrap_or(1.0) as f64;
+        self.needed.pop_front();
+
+        // Rounding in the average can leave a peak a hair over, which the clamp takes off.
+        let g = (self.sum / self.held.len() as f64) as f32;
+        (self.delayed.pop_front().unwrap_or(0.0) * g).clamp(-self.ceiling, self.ceiling)
+    }
+}
+
+/// Soft clip samples to ceiling, a linear level, leaving those below the knee untouched.
+pub fn soft_clip(samples: &mut [f32], ceiling: f32) {
+    let knee = SOFT_KNEE * ceiling;
+    let room = ceiling - knee;
+    for s in samples.iter_mut() {
+        let level = s.abs();
+        if level > knee {
+            // Same slope as the straight part at the knee, easing into the ceiling.
+            *s = s.signum() * (knee + room * ((level - knee) / room).tanh());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_peaks_between_samples() {
+        // A quarter sample rate sine at 45 degrees has every sample at 0.707 but peaks at 1.0
+        // between every other pair.
+        let sine: Vec<f32> = (0..1000).map(|i| {
+            (consts::FRAC_PI_2 * i as f64 + consts::FRAC_PI_4).sin() as f32
+        }).collect();
+        let peak = true_peak_envelope(&sine)[100..900].iter().fold(0.0f32, |m, e| m.max(*e));
+        assert!((peak - 1.0).abs() < 0.02, "true peak {}", peak);
+
+        let louder: Vec<f32> = sine.iter().map(|s| s * 1.2).collect();
+        let overs = find_overs(&louder);
+        assert_eq!(overs.samples, 0);
+        assert!(overs.between_samples > 450);
+        assert!((overs.true_peak - 20.0 * 1.2f64.log10()).abs() < 0.2);
+
+        // Measured a block at a time the count is the same.
+        let mut meter = OverMeter::new();
+        for block in louder.chunks(77) {
+            meter.process(block);
+        }
+        assert_eq!(meter.finish(), overs);
+    }
+
+    #[test]
+    fn limits_and_clips_to_the_ceiling() {
+        let sample_rate = 44100.0;
+        let mut left: Vec<f32> = (0..44100).map(|i| {
+            let t = i as f64 / sample_rate;
+            // A quiet tone with a loud burst in the middle.
+            let level = if t > 0.4 && t < 0.6 { 3.0 } else { 0.3 };
+            (level * (2.0 * consts::PI * 3000.0 * t).sin()) as f32
+        }).collect();
+        let mut right = left.clone();
+        let quiet = left[100];
+        limit(&mut [&mut left, &mut right], 0.5, DEFAULT_LOOKAHEAD, DEFAULT_LIMITER_RELEASE, sample_rate);
+        let peak = true_peak_envelope(&left).iter().fold(0.0f32, |m, e| m.max(*e));
+        assert!(peak < 0.51, "limited to {}", peak);
+        // Well away from the burst the level is left alone.
+        assert_eq!(left[100], quiet);
+        assert_eq!(left, right);
+
+        let mut loud: Vec<f32> = (0..1000).map(|i| (i as f32 / 100.0).sin() * 4.0).collect();
+        let mut limiter = Limiter::new(0.9, DEFAULT_LOOKAHEAD, DEFAULT_LIMITER_RELEASE, sample_rate);
+        let limited: Vec<f32> = loud.iter().map(|s| limiter.process(*s)).collect();
+        assert!(limited.iter().all(|s| s.abs() <= 0.9));
+        // Delayed by the lookahead.
+        let ahead = (DEFAULT_LOOKAHEAD * sample_rate).round() as usize;
+        assert!(limited[..ahead].iter().all(|s| *s == 0.0) && limited[ahead] != 0.0);
+
+        soft_clip(&mut loud, 0.9);
+        assert!(loud.iter().all(|s| s.abs() <= 0.9));
+    }
+}
@@ -0,0 +1,182 @@
+use std::f64::consts;
+
+use dsp::biquad::Biquad;
+
+// K-weighting from ITU-R BS.1770, a high shelf for the head followed by a high pass. These are
+// the analogue prototypes of the standard's 48kHz coefficients, so the filters can be built at
+// any sample rate.
+const SHELF_FREQUENCY: f64 = 1_681.974_450_955_533;
+const SHELF_GAIN: f64 = 3.999_843_853_973_347;
+const SHELF_Q: f64 = 0.707_175_236_955_419_6;
+const SHELF_BAND_EXPONENT: f64 = 0.499_666_774_154_541_6;
+const HIGH_PASS_FREQUENCY: f64 = 38.135_470_876_024_44;
+const HIGH_PASS_Q: f64 = 0.500_327_037_323_877_3;
+
+// Gating blocks are 400ms, starting every 100ms.
+const BLOCK_LENGTH: f64 = 0.4;
+const BLOCK_STEP: f64 = 0.1;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Level to normalize the output to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LevelTarget {
+    /// Sample peak in dBFS.
+    Peak(f64),
+    /// RMS in dBFS, a full scale sine reading 0dBFS as in AES17.
+    Rms(f64),
+    /// Integrated loudness in LUFS.
+    Lufs(f64),
+}
+
+impl LevelTarget {
+    /// Parse a level like -1, -1dBFS, -18rms or -23LUFS, peak if no unit is given.
+    pub fn from_spec(spec: &str) -> Result<LevelTarget, String> {
+        let lower = spec.trim().to_lowercase();
+        let (number, make): (&str, fn(f64) -> LevelTarget) = if let Some(n) = lower.strip_suffix("lufs") {
+            (n, LevelTarget::Lufs)
+        } else if let Some(n) = lower.strip_suffix("rms") {
+            (n, LevelTarget::Rms)
+        } else {
+            (lower.trim_end_matches("dbfs").trim_end_matches("db"), LevelTarget::Peak)
+        };
+        let level: f64 = number.trim().parse().map_err(|_| format!("bad level '{}'", spec))?;
+        Ok(make(level))
+    }
+}
+
+/// Largest sample magnitude of all channels in dBFS.
+pub fn peak_dbfs(channels: &[&[f32]]) -> f64 {
+    let peak = channels.iter().flat_map(|c| c.iter()).fold(0.0f32, |m, s| m.max(s.abs()));
+    20.0 * (peak as f64).log10()
+}
+
+/// RMS of all channels together in dBFS, a full scale sine reading 0dBFS.
+pub fn rms_dbfs(channels: &[&[f32]]) -> f64 {
+    let count: usize = channels.iter().map(|c| c.len()).sum();
+    let sum: f64 = channels.iter().flat_map(|c| c.iter()).map(|s| (*s as f64).powi(2)).sum();
+    10.0 * (sum / count.max(1) as f64).log10() + 10.0 * 2.0f64.log10()
+}
+
+/// K-weighting shelf and high pass at the sample rate.
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    let k = (consts::PI * SHELF_FREQUENCY / sample_rate).tan();
+    let high = 10.0f64.powf(SHELF_GAIN / 20.0);
+    let band = high.powf(SHELF_BAND_EXPONENT);
+    let a0 = 1.0 + k / SHELF_Q + k * k;
+    let shelf = Biquad::from_coefficients((high + band * k / SHELF_Q + k * k) / a0, 2.0 * (k * k - high) / a0,
+                                          (high - band * k / SHELF_Q + k * k) / a0, 2.0 * (k * k - 1.0) / a0,
+                                          (1.0 - k / SHELF_Q + k * k) / a0);
+
+    let k = (consts::PI * HIGH_PASS_FREQUENCY / sample_rate).tan();
+    let a0 = 1.0 + k / HIGH_PASS_Q + k * k;
+    let high_pass = Biquad::from_coefficients(1.0, -2.0, 1.0, 2.0 * (k * k - 1.0) / a0,
+                                              (1.0 - k / HIGH_PASS_Q + k * k) / a0);
+    (shelf, high_pass)
+}
+
+/// Integrated loudness in LUFS as ITU-R BS.1770, with each channel weighted as a front left or
+/// right.
+///
+/// Blocks quieter than -70 LUFS, or 10 LU below the loudness of those left, are gated out.
+/// Signals shorter than one block are measured as a single block.
+pub fn integrated_loudness(channels: &[&[f32]], sample_rate: f64) -> f64 {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    // Mean square of each channel after K-weighting, summed over channels per sample.
+    let mut power: Vec<f64> = vec![0.0; len];
+    for channel in channels.iter() {
+        let (mut shelf, mut high_pass) = k_weighting(sample_rate);
+        for (p, s) in power.iter_mut().zip(channel.iter()) {
+            *p += (high_pass.process(shelf.process(*s)) as f64).powi(2);
+        }
+    }
+
+    let block = (BLOCK_LENGTH * sample_rate).round() as usize;
+    let step = (BLOCK_STEP * sample_rate).round() as usize;
+    let mean = |p: &[f64]| p.iter().sum::<f64>() / p.len().max(1) as f64;
+    let blocks: Vec<f64> = if len < block {
+        vec![mean(&power)]
+    } else {
+        (0..(len - block) / step + 1).map(|i| mean(&power[i * step..i * step + block])).collect()
+    };
+    let loudness = |z: f64| -0.691 + 10.0 * z.log10();
+    let mean_above = |gate: f64| {
+        let kept: Vec<f64> = blocks.iter().cloned().filter(|z| loudness(*z) > gate).collect();
+        mean(&kept)
+    };
+
+    let relative = loudness(mean_above(ABSOLUTE_GATE)) + RELATIVE_GATE;
+    loudness(mean_above(relative.max(ABSOLUTE_GATE)))
+}
+
+/// Scale the channels together so they measure at the target level, returning the gain in dB.
+pub fn apply_level(target: LevelTarget, channels: &mut [&mut [f32]], sample_rate: f64) -> Result<f64, String> {
+    let (measured, wanted) = {
+        let views: Vec<&[f32]> = channels.iter().map(|c| &**c).collect();
+        match target {
+            LevelTarget::Peak(l) => (peak_dbfs(&views), l),
+            LevelTarget::Rms(l) => (rms_dbfs(&views), l),
+            LevelTarget::Lufs(l) => (integrated_loudness(&views, sample_rate), l),
+        }
+    };
+    if !measured.is_finite() {
+        return Err("can't set the level of silence".to_string());
+    }
+
+    let gain_db = wanted - measured;
+    let gain = 10.0f64.powf(gain_db / 20.0) as f32;
+    for s in channels.iter_mut().flat_map(|c| c.iter_mut()) {
+        *s *= gain;
+    }
+    Ok(gain_db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, seconds: f64, sample_rate: f64) -> Vec<f32> {
+        (0..(seconds * sample_rate) as usize)
+            .map(|i| (2.0 * consts::PI * frequency * i as f64 / sample_rate).sin() as f32).collect()
+    }
+
+    #[test]
+    fn full_scale_sine_levels() {
+        let sample_rate = 48000.0;
+        let tone = sine(997.0, 2.0, sample_rate);
+        assert!(peak_dbfs(&[&tone]).abs() < 0.01);
+        assert!(rms_dbfs(&[&tone]).abs() < 0.01);
+        // A full scale 1kHz sine is -3.01 LUFS in one channel, 0 in two.
+        assert!((integrated_loudness(&[&tone], sample_rate) + 3.01).abs() < 0.1);
+        assert!(integrated_loudness(&[&tone, &tone], sample_rate).abs() < 0.1);
+
+        // Silence after the tone is gated out, only the blocks overlapping its end pulling the
+        // level down, where ungated it would measure 6dB lower.
+        let mut gapped = tone.clone();
+        gapped.resize(tone.len() * 4, 0.0);
+        assert!((integrated_loudness(&[&gapped], sample_rate) + 3.01).abs() < 0.5);
+    }
+
+    #[test]
+    fn levels_are_applied() {
+        let sample_rate = 44100.0;
+        let mut tone = sine(440.0, 1.0, sample_rate);
+        assert_eq!(LevelTarget::from_spec("-18rms"), Ok(LevelTarget::Rms(-18.0)));
+        assert_eq!(LevelTarget::from_spec("-1dBFS"), Ok(LevelTarget::Peak(-1.0)));
+        for spec in ["-6", "-18rms", "-23LUFS"].iter() {
+            let target = LevelTarget::from_spec(spec).unwrap();
+            apply_level(target, &mut [&mut tone], sample_rate).unwrap();
+            let level = match target {
+                LevelTarget::Peak(_) => peak_dbfs(&[&tone]),
+                LevelTarget::Rms(_) => rms_dbfs(&[&tone]),
+                LevelTarget::Lufs(_) => integrated_loudness(&[&tone], sample_rate),
+            };
+            let wanted = match target {
+                LevelTarget::Peak(l) | LevelTarget::Rms(l) | LevelTarget::Lufs(l) => l,
+            };
+            assert!((level - wanted).abs() < 0.01, "{} measured {}", spec, level);
+        }
+        assert!(apply_level(LevelTarget::Peak(0.0), &mut [&mut [0.0f32; 10]], sample_rate).is_err());
+        assert!(LevelTarget::from_spec("loud").is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod biquad;
+pub mod vcf;
+pub mod loudness;
+pub mod limiter;
+pub mod delay;
+pub mod stereo;
+pub mod reverb;
@@ -0,0 +1,112 @@
+use dsp::delay::Effect;
+
+/// Room size of a reverb when none is given.
+pub const DEFAULT_REVERB_SIZE: f32 = 0.5;
+/// Damping of a reverb when none is given.
+pub const DEFAULT_REVERB_DAMPING: f32 = 0.5;
+/// Mix of a reverb when none is given.
+pub const DEFAULT_REVERB_MIX: f32 = 0.3;
+
+// Freeverb's comb and allpass lengths in samples at 44.1kHz.
+const COMB_LENGTHS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_LENGTHS: [usize; 4] = [556, 441, 341, 225];
+const ALLPASS_FEEDBACK: f32 = 0.5;
+// Input gain keeping the sum of the combs near the input level.
+const INPUT_GAIN: f32 = 0.015;
+
+// Feedback comb with a one pole low pass in the loop, so the highs die away first.
+#[derive(Debug, Clone)]
+struct Comb {
+    buffer: Vec<f32>,
+    pos: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn process(&mut self, x: f32, feedback: f32, damping: f32) -> f32 {
+        let out = self.buffer[self.pos];
+        self.filter_store = out * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.pos] = x + self.filter_store * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Allpass {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        self.buffer[self.pos] = x + delayed * ALLPASS_FEEDBACK;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+/// Schroeder reverb laid out as Freeverb, eight damped combs in parallel into four allpasses
+/// in series.
+#[derive(Debug, Clone)]
+pub struct Reverb {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+    feedback: f32,
+    damping: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    /// Reverb with room size, damping of the highs and mix from 0.0 to 1.0.
+    pub fn new(size: f32, damping: f32, mix: f32, sample_rate: f64) -> Reverb {
+        let scaled = |len: usize| ((len as f64 * sample_rate / 44100.0).round() as usize).max(1);
+        Reverb {
+            combs: COMB_LENGTHS.iter()
+                               .map(|l| Comb { buffer: vec![0.0; scaled(*l)], pos: 0, filter_store: 0.0 })
+                               .collect(),
+            allpasses: ALLPASS_LENGTHS.iter().map(|l| Allpass { buffer: vec![0.0; scaled(*l)], pos: 0 }).collect(),
+            feedback: 0.7 + size.clamp(0.0, 1.0) * 0.28,
+            damping: damping.clamp(0.0, 1.0) * 0.4,
+            mix: mix.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, x: f32) -> f32 {
+        let input = x * INPUT_GAIN;
+        let (feedback, damping) = (self.feedback, self.damping);
+        let mut wet: f32 = self.combs.iter_mut().map(|c| c.process(input, feedback, damping)).sum();
+        for allpass in self.allpasses.iter_mut() {
+            wet = allpass.process(wet);
+        }
+        x * (1.0 - self.mix) + wet * self.mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverb_tail_decays() {
+        let sample_rate = 44100.0;
+        let energy = |size: f32, damping: f32, from: usize, to: usize| {
+            let mut reverb = Reverb::new(size, damping, 1.0, sample_rate);
+            let mut samples = vec![0.0f32; 2 * 44100];
+            samples[0] = 1.0;
+            reverb.process_buffer(&mut samples);
+            samples[from..to].iter().map(|s| s * s).sum::<f32>()
+        };
+
+        // Nothing comes out before the shortest comb, then the tail dies away.
+        assert_eq!(energy(0.5, 0.5, 0, 1116), 0.0);
+        assert!(energy(0.5, 0.5, 1116, 22050) > 0.0);
+        assert!(energy(0.5, 0.5, 66150, 88200) < energy(0.5, 0.5, 1116, 22050) * 0.01);
+
+        // A bigger room rings longer.
+        assert!(energy(0.9, 0.5, 44100, 88200) > energy(0.3, 0.5, 44100, 88200));
+    }
+}
@@ -0,0 +1,150 @@
+use std::f64::consts;
+
+/// Left and right gains for pan from -1.0 (left) to 1.0 (right), equal power so a sound keeps
+/// its loudness as it moves.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) as f64 + 1.0) * consts::FRAC_PI_4;
+    (angle.cos() as f32, angle.sin() as f32)
+}
+
+/// Channels to flip the polarity of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    Normal,
+    InvertLeft,
+    InvertRight,
+    InvertBoth,
+}
+
+impl Polarity {
+    /// Parse the channels to invert as given on the command line.
+    pub fn from_name(name: &str) -> Option<Polarity> {
+        match name {
+            "none" => Some(Polarity::Normal),
+            "left" => Some(Polarity::InvertLeft),
+            "right" => Some(Polarity::InvertRight),
+            "both" => Some(Polarity::InvertBoth),
+            _ => None,
+        }
+    }
+}
+
+/// Changes to the stereo image made before writing.
+#[derive(Debug, Clone)]
+pub struct Imaging {
+    /// Position of a mono signal, or balance of a stereo one, -1.0 (left) to 1.0 (right).
+    pub pan: Option<f32>,
+    /// Level of the difference between the channels relative to their sum, 0.0 for mono, 1.0
+    /// leaving them alone and above 1.0 wider.
+    pub width: f32,
+    pub polarity: Polarity,
+    /// Time the right channel lags the left in seconds, negative for the left lagging.
+    pub delay: f64,
+}
+
+impl Default for Imaging {
+    fn default() -> Imaging {
+        Imaging { pan: None, width: 1.0, polarity: Polarity::Normal, delay: 0.0 }
+    }
+}
+
+impl Imaging {
+    /// Make the left and right channels from one channel, or from the two of a stereo signal.
+    ///
+    /// A mono signal is panned with equal power gains, so it is 3dB down in each channel in the
+    /// centre. A stereo one is balanced, the far channel turned down and the near left alone.
+    /// Delays are rounded to the sample, the lagging channel cut short to keep the length.
+    pub fn apply(&self, mut left: Vec<f32>, right: Option<Vec<f32>>, sample_rate: f64) -> (Vec<f32>, Vec<f32>) {
+        let mut right = match (right, self.pan) {
+            (Some(mut r), Some(p)) => {
+                let (l_gain, r_gain) = pan_gains(p);
+                let centre = consts::FRAC_1_SQRT_2 as f32;
+                scale(&mut left, (l_gain / centre).min(1.0));
+                scale(&mut r, (r_gain / centre).min(1.0));
+                r
+            }
+            (Some(r), None) => r,
+            (None, Some(p)) => {
+                let (l_gain, r_gain) = pan_gains(p);
+                let r = left.iter().map(|s| s * r_gain).collect();
+                scale(&mut left, l_gain);
+                r
+            }
+            (None, None) => left.clone(),
+        };
+
+        if self.width != 1.0 {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                let mid = (*l + *r) * 0.5;
+                let side = (*l - *r) * 0.5 * self.width;
+                *l = mid + side;
+                *r = mid - side;
+            }
+        }
+
+        match self.polarity {
+            Polarity::Normal => {}
+            Polarity::InvertLeft => scale(&mut left, -1.0),
+            Polarity::InvertRight => scale(&mut right, -1.0),
+            Polarity::InvertBoth => {
+                scale(&mut left, -1.0);
+                scale(&mut right, -1.0);
+            }
+        }
+
+        let lag = (self.delay.abs() * sample_rate).round() as usize;
+        if lag > 0 {
+            delay_channel(if self.delay > 0.0 { &mut right } else { &mut left }, lag);
+        }
+        (left, right)
+    }
+}
+
+fn scale(samples: &mut [f32], gain: f32) {
+    for s in samples.iter_mut() {
+        *s *= gain;
+    }
+}
+
+/// Delay samples by whole samples, keeping the length.
+fn delay_channel(samples: &mut [f32], lag: usize) {
+    let len = samples.len();
+    let lag = lag.min(len);
+    samples.copy_within(..len - lag, lag);
+    for s in samples[..lag].iter_mut() {
+        *s = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_mono_and_stereo() {
+        let mono: Vec<f32> = (0..100).map(|i| (i as f32 * 0.1).sin()).collect();
+
+        // Panned to the centre each channel is 3dB down, the power the same as the mono.
+        let (l, r) = Imaging { pan: Some(0.0), ..Imaging::default() }.apply(mono.clone(), None, 1000.0);
+        assert_eq!(l, r);
+        let power = |c: &[f32]| c.iter().map(|s| s * s).sum::<f32>();
+        assert!((power(&l) + power(&r) - power(&mono)).abs() < 1.0e-3);
+        let (l, r) = Imaging { pan: Some(-1.0), ..Imaging::default() }.apply(mono.clone(), None, 1000.0);
+        assert!(r.iter().all(|s| s.abs() < 1.0e-6) && (power(&l) - power(&mono)).abs() < 1.0e-3);
+
+        // Balance leaves the near channel alone.
+        let (l, r) = Imaging { pan: Some(0.5), ..Imaging::default() }.apply(mono.clone(), Some(mono.clone()), 1000.0);
+        assert_eq!(r, mono);
+        assert!(power(&l) < power(&mono) * 0.5);
+
+        // No width is the same on both channels, inverted the right cancels it.
+        let imaging = Imaging { width: 0.0, polarity: Polarity::InvertRight, ..Imaging::default() };
+        let (l, r) = imaging.apply(mono.clone(), Some(vec![0.0; 100]), 1000.0);
+        assert!(l.iter().zip(r.iter()).all(|(a, b)| a + b == 0.0));
+
+        let (l, r) = Imaging { delay: -0.003, ..Imaging::default() }.apply(mono.clone(), None, 1000.0);
+        assert_eq!(r, mono);
+        assert_eq!(&l[..3], &[0.0; 3]);
+        assert_eq!(&l[3..], &mono[..97]);
+    }
+}
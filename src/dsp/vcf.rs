@@ -0,0 +1,167 @@
+use std::f64::consts;
+
+/// Filter whose cutoff and resonance can change on every sample.
+pub trait ModulatedFilter {
+    /// Filter one sample with cutoff in Hz and resonance from 0.0 to 1.0.
+    fn process(&mut self, x: f32, cutoff: f64, resonance: f64) -> f32;
+}
+
+/// Output tap of the state variable filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SvfMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+impl SvfMode {
+    /// Parse a mode name as given on the command line.
+    pub fn from_name(name: &str) -> Option<SvfMode> {
+        match name {
+            "lp" => Some(SvfMode::LowPass),
+            "hp" => Some(SvfMode::HighPass),
+            "bp" => Some(SvfMode::BandPass),
+            "notch" => Some(SvfMode::Notch),
+            _ => None,
+        }
+    }
+}
+
+/// Topology preserving transform state variable filter, after Zavalishin and Simper.
+///
+/// Stays stable and in tune with the cutoff changing every sample, unlike a biquad.
+#[derive(Debug, Clone)]
+pub struct Svf {
+    mode: SvfMode,
+    ic1eq: f64,
+    ic2eq: f64,
+    sample_rate: f64,
+}
+
+impl Svf {
+    pub fn new(mode: SvfMode, sample_rate: f64) -> Svf {
+        Svf { mode, ic1eq: 0.0, ic2eq: 0.0, sample_rate }
+    }
+}
+
+impl ModulatedFilter for Svf {
+    fn process(&mut self, x: f32, cutoff: f64, resonance: f64) -> f32 {
+        let cutoff = cutoff.clamp(1.0, self.sample_rate * 0.49);
+        let g = (consts::PI * cutoff / self.sample_rate).tan();
+        // Resonance 0.0 is Q 0.5, approaching 1.0 the damping goes to zero.
+        let k = 2.0 - 2.0 * resonance.clamp(0.0, 0.99);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v0 = x as f64;
+        let v3 = v0 - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        let out = match self.mode {
+            SvfMode::LowPass => v2,
+            SvfMode::BandPass => v1,
+            SvfMode::HighPass => v0 - k * v1 - v2,
+            SvfMode::Notch => v0 - k * v1,
+        };
+        out as f32
+    }
+}
+
+/// Four pole Moog style transistor ladder low pass, after Huovilainen's nonlinear model.
+///
+/// Resonance 1.0 is the edge of self oscillation. Each stage saturates, so driving the input
+/// harder thickens the sound rather than clipping.
+#[derive(Debug, Clone)]
+pub struct Ladder {
+    stage: [f64; 4],
+    stage_tanh: [f64; 3],
+    sample_rate: f64,
+}
+
+impl Ladder {
+    pub fn new(sample_rate: f64) -> Ladder {
+        Ladder { stage: [0.0; 4], stage_tanh: [0.0; 3], sample_rate }
+    }
+}
+
+impl ModulatedFilter for Ladder {
+    fn process(&mut self, x: f32, cutoff: f64, resonance: f64) -> f32 {
+        let cutoff = cutoff.clamp(1.0, self.sample_rate * 0.45);
+        let g = 1.0 - (-2.0 * consts::PI * cutoff / self.sample_rate).exp();
+        let feedback = 4.0 * resonance.clamp(0.0, 1.0);
+
+        let input = x as f64 - feedback * self.stage[3];
+        self.stage[0] += g * (input.tanh() - self.stage_tanh[0]);
+        for i in 1..4 {
+            self.stage_tanh[i - 1] = self.stage[i - 1].tanh();
+            let prev = self.stage_tanh[i - 1];
+            let own = if i < 3 { self.stage_tanh[i] } else { self.stage[3].tanh() };
+            self.stage[i] += g * (prev - own);
+        }
+        // Compensate the passband loss that comes with resonance.
+        (self.stage[3] * (1.0 + feedback * 0.5)) as f32
+    }
+}
+
+/// Run samples through a modulated filter, cutoff in Hz and resonance given per sample.
+pub fn apply_modulated<F: ModulatedFilter>(filter: &mut F, samples: &mut [f32], cutoff: &[f32], resonance: &[f32]) {
+    for ((s, c), r) in samples.iter_mut().zip(cutoff.iter()).zip(resonance.iter()) {
+        *s = filter.process(*s, *c as f64, *r as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synth::oscillator::SineOscillator;
+
+    fn peak_after_settle<F: ModulatedFilter>(filter: &mut F, frequency: f64, cutoff: f64) -> f32 {
+        let mut osc = SineOscillator::new(48000.0);
+        let mut peak = 0.0f32;
+        for i in 0..48000 {
+            let y = filter.process(osc.tick(frequency) * 0.1, cutoff, 0.0);
+            if i > 24000 {
+                peak = peak.max(y.abs());
+            }
+        }
+        peak / 0.1
+    }
+
+    #[test]
+    fn low_pass_responses() {
+        let mut svf = Svf::new(SvfMode::LowPass, 48000.0);
+        assert!(peak_after_settle(&mut svf, 100.0, 1000.0) > 0.98);
+        let mut svf = Svf::new(SvfMode::LowPass, 48000.0);
+        assert!(peak_after_settle(&mut svf, 10000.0, 1000.0) < 0.02);
+
+        let mut ladder = Ladder::new(48000.0);
+        assert!(peak_after_settle(&mut ladder, 100.0, 1000.0) > 0.95);
+        let mut ladder = Ladder::new(48000.0);
+        assert!(peak_after_settle(&mut ladder, 10000.0, 1000.0) < 0.001);
+    }
+
+    #[test]
+    fn resonance_follows_its_control() {
+        let mut osc = SineOscillator::new(48000.0);
+        let input: Vec<f32> = (0..4800).map(|_| osc.tick(1000.0) * 0.1).collect();
+        let cutoff = vec![1000.0f32; 4800];
+        let render = |resonance: &[f32]| {
+            let mut samples = input.clone();
+            apply_modulated(&mut Svf::new(SvfMode::LowPass, 48000.0), &mut samples, &cutoff, resonance);
+            samples
+        };
+
+        // Resonance rising halfway through leaves the first half alone and lifts the cutoff.
+        let flat = render(&[0.0; 4800]);
+        let rising: Vec<f32> = (0..4800).map(|i| if i < 2400 { 0.0 } else { 0.9 }).collect();
+        let swept = render(&rising);
+        assert_eq!(&swept[..2400], &flat[..2400]);
+        let peak = |s: &[f32]| s.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+        assert!(peak(&swept[3600..]) > peak(&flat[3600..]) * 2.0);
+    }
+}
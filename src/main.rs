@@ -4,17 +4,43 @@ extern crate rand;
 
 use std::env;
 use std::fs::File;
-use std::io::copy;
+use std::io;
 
-use wavfile::{create_wav, create_mono_datachunk, create_stereo_datachunk};
+use wavfile::WavWriter;
 //use wavfile::F32Sample;
 
 mod synth;
-use synth::ksstring::{generate_one_pluck_sample, generate_ks_threshold};
-use synth::tone::generate_tone_f32;
+use synth::generator::{Generator, render};
+use synth::ksstring::{KarplusStrong, generate_one_pluck_sample, generate_ks_retrigger, generate_pluck_lfo_damping,
+                      generate_pluck_glide};
+use synth::tone::{generate_tone_f32, tone_length};
+use synth::multitone::{Partial, PhaseMode, parse_partials, apply_phase_mode, generate_multitone};
+use synth::telephony::{CallProgress, Region, generate_dtmf, generate_call_progress};
+use synth::fm::{Algorithm, preset_from_name, generate_fm};
+use synth::additive::{load_partial_tracks, generate_additive};
+use synth::lfo::{Lfo, LfoShape, LfoTarget, amplitude_modulate, ring_modulate, generate_vibrato_tone};
+use synth::noise::generate_white_noise;
+use synth::percussion::{Percussion, generate_percussion};
+use synth::voice::{SineVoice, PluckVoice, VoiceAllocator, render_notes, strum};
+use synth::smf::{load_smf, render_song};
+use synth::sequence::{parse_sequence, load_sequence, generate_sequence};
+use synth::patch::load_patch;
+use synth::pitch::parse_pitch;
+use synth::oscillator::{Tone, Waveform, generate_waveform};
+use synth::envelope::{Envelope, Curve};
+use synth::sweep::{SweepKind, generate_sweep, inverse_filter};
+
+mod dsp;
+use dsp::biquad::parse_filter_chain;
+use dsp::vcf::{Svf, SvfMode, Ladder, apply_modulated};
+use dsp::loudness::{LevelTarget, apply_level};
+use dsp::limiter::{DEFAULT_LOOKAHEAD, DEFAULT_LIMITER_RELEASE, Overs, OverMeter, limit, soft_clip, find_overs};
 
 mod options;
 
+// Samples per channel in each block written to the file.
+const BLOCK_SIZE: usize = 4096;
+
 fn main() {
     let sample_rate = 44100.0;
     let opts = options::setup_options();
@@ -44,48 +70,559 @@ fn main() {
         return;
     }
 
-    let runtime: f64 = matches.opt_str("length").expect("Error: length parameter")
-                              .parse().ok().expect("Error: length parameter");
-    let freq: f64 = matches.opt_str("frequency").expect("Error: frequency parameter")
-                           .parse().ok().expect("Error: frequency parameter");
     let filename: String = matches.opt_str("out-file").expect("Error: Filename parameter");
+    let stereo = matches.opt_present("stereo");
 
-    if (runtime > 0.0) && (freq > 0.0) {
-        let stereo = matches.opt_present("stereo");
+    let runtime: f64 = options::opt_f64(&matches, "length").unwrap_or(0.0);
+    let tuning = match options::tuning_from_matches(&matches) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error: tuning parameter, {}", e);
+            return;
+        }
+    };
+    let mut partials = match matches.opt_str("frequency").map(|f| parse_partials(&f, &tuning)) {
+        Some(Ok(p)) => p,
+        Some(Err(e)) => {
+            println!("Error: frequency parameter, {}", e);
+            return;
+        }
+        None => Vec::new(),
+    };
+    let freq: f64 = partials.first().map(|p| p.frequency).unwrap_or(0.0);
 
-        let chan_one = if matches.opt_present("k") {
-            if matches.opt_present("r") {
-                let thresh: f64 = matches.opt_str("r").unwrap()
-                                  .parse().ok().expect("Could not parse THRESHOLD");
-                generate_ks_threshold(runtime, freq, sample_rate, thresh)
-            } else {
-                generate_one_pluck_sample(runtime, freq, sample_rate)
+    let mut lfo = options::lfo_from_matches(&matches, sample_rate);
+    let lfo_target: Option<LfoTarget> = lfo.as_ref().map(|l| l.1);
+
+    let sweep_kind: Option<SweepKind> = matches.opt_str("sweep").map(|s| {
+        SweepKind::from_name(&s).expect("Error: sweep parameter")
+    });
+    let end_freq = match matches.opt_str("end-frequency").map(|f| parse_pitch(&f, &tuning)) {
+        Some(Ok(f)) if f > 0.0 && f.is_finite() => Some(f),
+        Some(Ok(f)) => {
+            println!("Error: end-frequency parameter, {}Hz isn't above 0Hz", f);
+            return;
+        }
+        Some(Err(e)) => {
+            println!("Error: end-frequency parameter, {}", e);
+            return;
+        }
+        None => None,
+    };
+
+    let mut patch = match matches.opt_str("patch") {
+        Some(path) => {
+            let loaded = options::string_from_matches(&matches)
+                                 .map_err(|e| format!("excitation parameter, {}", e))
+                                 .and_then(|s| {
+                                     load_patch(&path, &tuning, &s, sample_rate)
+                                         .map_err(|e| format!("patch parameter, {}", e))
+                                 });
+            match loaded {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
             }
+        }
+        None => None,
+    };
+
+    // Patches, plain tones and plucks are streamed to the file, however long they are.
+    let plain = partials.len() == 1 && partials[0] == Partial::new(freq);
+    if options::can_stream(&args[1..]) && runtime > 0.0 && (patch.is_some() || plain && freq > 0.0) {
+        // The same length as the whole file path, the sine tone keeping its extra sample.
+        let num_samples = if patch.is_none() && !matches.opt_present("k") && !matches.opt_present("waveform") {
+            tone_length(runtime, sample_rate)
         } else {
-            generate_tone_f32(runtime, freq, sample_rate)
+            (runtime * sample_rate).round() as usize
         };
+        let source: Box<dyn Generator> = match patch {
+            Some(p) => Box::new(p),
+            None => match plain_source(&matches, freq, 0.0, sample_rate) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            },
+        };
+        let source: Box<dyn Generator> = match matches.opt_str("filter").map(|f| parse_filter_chain(&f, sample_rate)) {
+            Some(Ok(mut chain)) => Box::new(source.chain(move |b: &mut [f32]| chain.process_buffer(b))),
+            Some(Err(e)) => {
+                println!("Error: filter parameter, {}", e);
+                return;
+            }
+            None => source,
+        };
+        let source: Box<dyn Generator> = match options::effects_from_matches(&matches, sample_rate) {
+            Ok(ref e) if e.is_empty() => source,
+            Ok(mut effects) => Box::new(source.chain(move |b: &mut [f32]| {
+                for effect in effects.iter_mut() {
+                    effect.process_buffer(b);
+                }
+            })),
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = stream_wav(&filename, source, num_samples, stereo) {
+            println!("Error: {}: {}", filename, e);
+        }
+        return;
+    }
 
-        let mut chan_two: Vec<f32> = Vec::new();
-        
-        if stereo {
-            chan_two = chan_one.clone()
+    // Set by generators that make their own stereo.
+    let mut chan_two: Option<Vec<f32>> = None;
+
+    let mut chan_one = if matches.free.first().map(|s| s.as_str()) == Some("render") {
+        let path = match matches.free.get(1) {
+            Some(p) => p,
+            None => {
+                println!("Error: render parameter, no MIDI file given");
+                return;
+            }
+        };
+        let song = match load_smf(path) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: render parameter, {}", e);
+                return;
+            }
+        };
+        let string = match options::string_from_matches(&matches) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: excitation parameter, {}", e);
+                return;
+            }
+        };
+        // Without a length, play to the end of the song and let the last notes ring.
+        let run_length = if runtime > 0.0 { runtime } else { song.length + 1.0 };
+        let voices = options::opt_f64(&matches, "voices").map_or(16, |v| v as usize);
+        let (left, right) = render_song(&song, &tuning, &string, voices, run_length, sample_rate);
+        chan_two = Some(right);
+        left
+    } else if let Some(ref mut p) = patch {
+        if runtime <= 0.0 {
+            println!("Please enter sane values for parameters.");
+            return;
         }
-        
-        let dc = if stereo {
-            create_stereo_datachunk(chan_one, chan_two)
+        render(p, (runtime * sample_rate).round() as usize)
+    } else if matches.opt_present("sequence") || matches.opt_present("sequence-file") {
+        let steps = match (matches.opt_str("sequence"), matches.opt_str("sequence-file")) {
+            (Some(text), _) => parse_sequence(&text, &tuning),
+            (None, Some(path)) => load_sequence(&path, &tuning),
+            (None, None) => unreachable!(),
+        };
+        let steps = match steps {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: sequence parameter, {}", e);
+                return;
+            }
+        };
+        let string = match options::string_from_matches(&matches) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: excitation parameter, {}", e);
+                return;
+            }
+        };
+        generate_sequence(&steps, &string, sample_rate)
+    } else if let Some(digits) = matches.opt_str("dtmf") {
+        let tone_length = options::opt_f64(&matches, "tone-length").unwrap_or(0.1);
+        let gap = options::opt_f64(&matches, "gap").unwrap_or(0.1);
+        let twist = options::opt_f64(&matches, "twist").unwrap_or(0.0);
+        match generate_dtmf(&digits, tone_length, gap, twist, sample_rate) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Error: dtmf parameter, {}", e);
+                return;
+            }
+        }
+    } else if let Some(name) = matches.opt_str("call-progress") {
+        let tone = CallProgress::from_name(&name).expect("Error: call-progress parameter");
+        let region = match matches.opt_str("region") {
+            Some(r) => Region::from_name(&r).expect("Error: region parameter"),
+            None => Region::NorthAmerica,
+        };
+        if runtime <= 0.0 {
+            println!("Please enter sane values for parameters.");
+            return;
+        }
+        generate_call_progress(runtime, tone, region, sample_rate)
+    } else if let Some(path) = matches.opt_str("additive") {
+        let tracks = match load_partial_tracks(&path) {
+            Ok(t) => t,
+            Err(e) => {
+                println!("Error: additive parameter, {}", e);
+                return;
+            }
+        };
+        // Without a length, play until the last breakpoint of any track.
+        let run_length = if runtime > 0.0 {
+            runtime
         } else {
-            create_mono_datachunk(chan_one)
+            tracks.iter().map(|t| t.duration()).fold(0.0, f64::max)
+        };
+        generate_additive(run_length, &tracks, matches.opt_present("band-limit"), sample_rate)
+    } else if matches.opt_present("noise") {
+        if runtime <= 0.0 {
+            println!("Please enter sane values for parameters.");
+            return;
+        }
+        generate_white_noise(runtime, options::opt_seed(&matches), sample_rate)
+    } else if matches.opt_present("left") || matches.opt_present("right") || matches.opt_present("phase-offset") {
+        // Each channel from its own generator, at --frequency unless given its own.
+        let channel_freq = |name: &str| -> Result<f64, String> {
+            match matches.opt_str(name) {
+                Some(f) => parse_pitch(&f, &tuning).map_err(|e| format!("{} parameter, {}", name, e)),
+                None if freq > 0.0 => Ok(freq),
+                None => Err(format!("{} parameter, no frequency for the {} channel", name, name)),
+            }
+        };
+        let phase = options::opt_f64(&matches, "phase-offset").unwrap_or(0.0);
+        let sources = channel_freq("left").and_then(|l| {
+            let r = channel_freq("right")?;
+            Ok((plain_source(&matches, l, 0.0, sample_rate)?, plain_source(&matches, r, phase, sample_rate)?))
+        });
+        let (mut left, mut right) = match sources {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
         };
+        if runtime <= 0.0 {
+            println!("Please enter sane values for parameters.");
+            return;
+        }
+        let num_samples = (runtime * sample_rate).round() as usize;
+        chan_two = Some(render(&mut right, num_samples));
+        render(&mut left, num_samples)
+    } else if (runtime > 0.0) && (freq > 0.0) {
+        if let Some(kind) = sweep_kind {
+            let end_freq = end_freq.expect("Error: end-frequency parameter required for sweep");
+            let fade_in = options::opt_f64(&matches, "sweep-fade-in").unwrap_or(0.0);
+            let fade_out = options::opt_f64(&matches, "sweep-fade-out").unwrap_or(0.0);
+            generate_sweep(runtime, freq, end_freq, kind, fade_in, fade_out, sample_rate)
+        } else if matches.opt_present("chord") {
+            let frequencies: Vec<f64> = partials.iter().map(|p| p.frequency).collect();
+            let velocities: Vec<f32> = partials.iter().map(|p| p.amplitude.min(1.0)).collect();
+            let gap = options::opt_f64(&matches, "strum").unwrap_or(0.0);
+            let spread = options::opt_f64(&matches, "spread").unwrap_or(0.6) as f32;
+            let notes = strum(&frequencies, &velocities, gap, spread, runtime);
+            let voices = options::opt_f64(&matches, "voices").map_or(partials.len(), |v| v as usize);
 
-        let mut wav = create_wav(dc, 44100, 32);
+            let (left, right) = if matches.opt_present("k") {
+                let string = match options::string_from_matches(&matches) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("Error: excitation parameter, {}", e);
+                        return;
+                    }
+                };
+                let plucks = (0..voices).map(|_| PluckVoice::new(&string, sample_rate)).collect();
+                render_notes(&mut VoiceAllocator::new(plucks), &notes, runtime, sample_rate)
+            } else {
+                let sines = (0..voices).map(|_| SineVoice::new(sample_rate)).collect();
+                render_notes(&mut VoiceAllocator::new(sines), &notes, runtime, sample_rate)
+            };
+            chan_two = Some(right);
+            left
+        } else if matches.opt_present("k") || matches.opt_present("percussion") {
+            let mut string = match options::string_from_matches(&matches) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("Error: excitation parameter, {}", e);
+                    return;
+                }
+            };
+            let retrigger = match options::retrigger_from_matches(&matches) {
+                Ok(r) => r,
+                Err(e) => {
+                    println!("Error: repeat parameter, {}", e);
+                    return;
+                }
+            };
+            if let Some(name) = matches.opt_str("percussion") {
+                let kind = Percussion::from_name(&name).expect("Error: percussion parameter");
+                if !matches.opt_present("blend") {
+                    string.blend = kind.default_blend();
+                }
+                generate_percussion(runtime, freq, kind, &string, sample_rate)
+            } else if let Some(retrigger) = retrigger {
+                generate_ks_retrigger(runtime, freq, &string, &retrigger, sample_rate)
+            } else if lfo_target == Some(LfoTarget::Damping) {
+                generate_pluck_lfo_damping(runtime, freq, &string, sample_rate,
+                                           lfo.as_mut().map(|l| &mut l.0).unwrap())
+            } else if matches.opt_present("glide") || lfo_target == Some(LfoTarget::Frequency) {
+                let end_freq = end_freq.unwrap_or(freq);
+                let glide = options::opt_f64(&matches, "glide").unwrap_or(0.0);
+                let vibrato = match lfo {
+                    Some((ref mut l, LfoTarget::Frequency)) => Some(l),
+                    _ => None,
+                };
+                generate_pluck_glide(runtime, freq, end_freq, glide, &string, sample_rate, vibrato)
+            } else {
+                generate_one_pluck_sample(runtime, freq, &string, sample_rate)
+            }
+        } else if let Some(preset) = matches.opt_str("fm") {
+            let mut voice = preset_from_name(&preset, runtime, sample_rate).expect("Error: fm parameter");
+            if let Some(n) = matches.opt_str("algorithm") {
+                let algorithm = n.parse().ok().and_then(Algorithm::number).expect("Error: algorithm parameter");
+                voice.set_algorithm(algorithm);
+            }
+            generate_fm(runtime, freq, &mut voice, sample_rate)
+        } else if partials.len() > 1 || partials[0] != Partial::new(freq) || matches.opt_present("phases") {
+            if let Some(mode) = matches.opt_str("phases") {
+                apply_phase_mode(&mut partials, PhaseMode::from_name(&mode).expect("Error: phases parameter"));
+            }
+            generate_multitone(runtime, &partials, sample_rate)
+        } else if lfo_target == Some(LfoTarget::Frequency) {
+            generate_vibrato_tone(runtime, freq, lfo.as_mut().map(|l| &mut l.0).unwrap(), sample_rate)
+        } else if let Some(name) = matches.opt_str("waveform") {
+            let waveform = Waveform::from_name(&name).expect("Error: waveform parameter");
+            generate_waveform(runtime, freq, waveform, sample_rate)
+        } else {
+            generate_tone_f32(runtime, freq, sample_rate)
+        }
+    } else {
+        println!("Please enter sane values for parameters.");
+        return;
+    };
+
+    // The second channel gets the same modulation from its own copy of the LFO.
+    let mut lfo_two = lfo.clone();
+    if let Err(e) = post_process(&matches, &mut chan_one, &mut lfo, sample_rate) {
+        println!("Error: {}", e);
+        return;
+    }
+    if let Some(ref mut two) = chan_two {
+        if let Err(e) = post_process(&matches, two, &mut lfo_two, sample_rate) {
+            println!("Error: {}", e);
+            return;
+        }
+    }
 
-        let mut f = File::create(filename).unwrap();
-        let _ = copy( &mut wav.header, &mut f);
-        let _ = copy( &mut wav.format_chunk, &mut f);
-        let _ = copy( &mut wav.data, &mut f);
-        let _ = f.sync_all();
+    if let (Some(kind), Some(inv_name)) = (sweep_kind, matches.opt_str("inverse-file")) {
+        let inverse = inverse_filter(&chan_one, freq, end_freq.unwrap(), kind);
+        write_wav(&inv_name, inverse, None);
+    }
+
+    match options::imaging_from_matches(&matches) {
+        Ok(Some(imaging)) => {
+            let (left, right) = imaging.apply(chan_one, chan_two.take(), sample_rate);
+            chan_one = left;
+            chan_two = Some(right);
+        }
+        Ok(None) => {
+            if stereo && chan_two.is_none() {
+                chan_two = Some(chan_one.clone());
+            }
+        }
+        Err(e) => {
+            println!("Error: invert parameter, {}", e);
+            return;
+        }
+    }
+
+    if let Some(spec) = matches.opt_str("level") {
+        let mut channels: Vec<&mut [f32]> = vec![&mut chan_one];
+        if let Some(ref mut two) = chan_two {
+            channels.push(two);
+        }
+        if let Err(e) = LevelTarget::from_spec(&spec).and_then(|t| apply_level(t, &mut channels, sample_rate)) {
+            println!("Error: level parameter, {}", e);
+            return;
+        }
+    }
+
+    // Protect the output from overs, then own up to any left.
+    let to_linear = |db: f64| 10.0f64.powf(db / 20.0) as f32;
+    if let Some(ceiling) = options::opt_f64(&matches, "limit") {
+        let lookahead = options::opt_f64(&matches, "lookahead").unwrap_or(DEFAULT_LOOKAHEAD);
+        let mut channels: Vec<&mut [f32]> = vec![&mut chan_one];
+        if let Some(ref mut two) = chan_two {
+            channels.push(two);
+        }
+        limit(&mut channels, to_linear(ceiling), lookahead, DEFAULT_LIMITER_RELEASE, sample_rate);
+    }
+    if let Some(ceiling) = options::opt_f64(&matches, "soft-clip") {
+        soft_clip(&mut chan_one, to_linear(ceiling));
+        if let Some(ref mut two) = chan_two {
+            soft_clip(two, to_linear(ceiling));
+        }
+    }
+    warn_overs("left", find_overs(&chan_one));
+    if let Some(ref two) = chan_two {
+        warn_overs("right", find_overs(two));
+    }
+
+    write_wav(&filename, chan_one, chan_two);
+}
+
+/// Filters, modulation, envelopes and then delays and reverb applied to a generated channel.
+fn post_process(matches: &options::Matches, samples: &mut [f32], lfo: &mut Option<(Lfo, LfoTarget)>,
+                sample_rate: f64) -> Result<(), String> {
+    if let Some(spec) = matches.opt_str("filter") {
+        let mut chain = parse_filter_chain(&spec, sample_rate).map_err(|e| format!("filter parameter, {}", e))?;
+        chain.process_buffer(samples);
+    }
+
+    apply_vcf(matches, samples, lfo, sample_rate)?;
+
+    if let Some((ref mut l, LfoTarget::Amplitude)) = *lfo {
+        amplitude_modulate(samples, l);
+    }
+    if let Some(am_freq) = options::opt_f64(matches, "am") {
+        let depth = options::opt_f64(matches, "am-depth").unwrap_or(1.0) as f32;
+        amplitude_modulate(samples, &mut Lfo::new(LfoShape::Sine, am_freq, depth, None, sample_rate));
+    }
+    if let Some(rm_freq) = options::opt_f64(matches, "ring-mod") {
+        ring_modulate(samples, &mut Lfo::new(LfoShape::Sine, rm_freq, 1.0, None, sample_rate));
+    }
+
+    // Envelopes cover the rendered length, which for DTMF comes from the digit string.
+    let run_length: f64 = samples.len() as f64 / sample_rate;
+    if let Some(env) = options::adsr_from_matches(matches, run_length) {
+        env.apply(samples, sample_rate);
+    }
+    if let Some(env) = options::fade_from_matches(matches, run_length) {
+        env.apply(samples, sample_rate);
+    }
+
+    for mut effect in options::effects_from_matches(matches, sample_rate)? {
+        effect.process_buffer(samples);
+    }
+    Ok(())
+}
+
+/// Run the --svf or --ladder filter, with its cutoff swept by --filter-env and the cutoff LFO,
+/// and its resonance by the resonance LFO.
+fn apply_vcf(matches: &options::Matches, samples: &mut [f32], lfo: &mut Option<(Lfo, LfoTarget)>,
+             sample_rate: f64) -> Result<(), String> {
+    let (spec, name) = match (matches.opt_str("svf"), matches.opt_str("ladder")) {
+        (Some(s), _) => (s, "svf"),
+        (None, Some(l)) => (l, "ladder"),
+        (None, None) => return Ok(()),
+    };
+    let fields: Vec<&str> = spec.split(':').collect();
+    let first_num = if name == "svf" { 1 } else { 0 };
+    let num = |i: usize, default: Option<f64>| -> Result<f64, String> {
+        match fields.get(i) {
+            Some(f) => f.parse().map_err(|_| format!("{} parameter, bad number '{}'", name, f)),
+            None => default.ok_or_else(|| format!("{} parameter, missing cutoff", name)),
+        }
+    };
+    let cutoff = num(first_num, None)?;
+    let resonance = num(first_num + 1, Some(0.0))?;
+
+    // Cutoff per sample, in octaves above the base cutoff until converted to Hz.
+    let num_samples = samples.len();
+    let run_length = num_samples as f64 / sample_rate;
+    let mut octaves: Vec<f32> = vec![0.0; num_samples];
+    if let Some(amount) = options::opt_f64(matches, "filter-env") {
+        let env = options::adsr_from_matches(matches, run_length)
+                          .unwrap_or_else(|| Envelope::ar(0.0, run_length, 0.0, Curve::Exponential));
+        for (o, e) in octaves.iter_mut().zip(env.render(num_samples, sample_rate)) {
+            *o += e * amount as f32;
+        }
+    }
+    if let Some((ref mut l, LfoTarget::Cutoff)) = *lfo {
+        for o in octaves.iter_mut() {
+            *o += l.tick();
+        }
+    }
+    let cutoff_hz: Vec<f32> = octaves.iter().map(|o| (cutoff * (*o as f64).exp2()) as f32).collect();
+    let mut resonance: Vec<f32> = vec![resonance as f32; num_samples];
+    if let Some((ref mut l, LfoTarget::Resonance)) = *lfo {
+        for r in resonance.iter_mut() {
+            *r += l.tick();
+        }
+    }
+
+    if name == "svf" {
+        let mode = SvfMode::from_name(fields[0]).ok_or("svf parameter, unknown mode")?;
+        apply_modulated(&mut Svf::new(mode, sample_rate), samples, &cutoff_hz, &resonance);
+    } else {
+        apply_modulated(&mut Ladder::new(sample_rate), samples, &cutoff_hz, &resonance);
+    }
+    Ok(())
+}
+
+/// Tone, waveform or pluck at frequency, the tone starting phase degrees into its cycle.
+fn plain_source(matches: &options::Matches, frequency: f64, phase: f64,
+                sample_rate: f64) -> Result<Box<dyn Generator>, String> {
+    if matches.opt_present("k") {
+        if phase != 0.0 {
+            return Err("phase-offset parameter, only tones have a phase".to_string());
+        }
+        let string = options::string_from_matches(matches).map_err(|e| format!("excitation parameter, {}", e))?;
+        let mut ks = KarplusStrong::with_params(frequency, &string, sample_rate);
+        ks.pluck();
+        Ok(Box::new(ks))
     } else {
-        println!("Please enter sane values for parameters.")
+        let waveform = matches.opt_str("waveform").map_or(Waveform::Sine, |name| {
+            Waveform::from_name(&name).expect("Error: waveform parameter")
+        });
+        Ok(Box::new(Tone::new(waveform, frequency, sample_rate).with_phase(phase)))
+    }
+}
+
+/// Own up to overs left in a channel.
+fn warn_overs(name: &str, overs: Overs) {
+    if overs.samples > 0 || overs.between_samples > 0 {
+        eprintln!("Warning: {} channel has {} samples over full scale and {} more overs between \
+                   samples, true peak {:.2} dBTP.", name, overs.samples, overs.between_samples,
+                  overs.true_peak);
+    }
+}
+
+/// Write num_samples from a generator to a 32bit .wav file a block at a time, the same on both
+/// channels if stereo, and own up to any overs.
+fn stream_wav<G: Generator>(filename: &str, mut source: G, num_samples: usize, stereo: bool) -> io::Result<()> {
+    let mut writer = WavWriter::new(File::create(filename)?, if stereo { 2 } else { 1 }, 44100)?;
+    let mut meter = OverMeter::new();
+    let mut buffer: Vec<f32> = vec![0.0; BLOCK_SIZE];
+    let mut written = 0;
+    while written < num_samples {
+        let block = &mut buffer[..BLOCK_SIZE.min(num_samples - written)];
+        source.process(block);
+        meter.process(block);
+        if stereo {
+            writer.write_channels(&[block, block])?;
+        } else {
+            writer.write_samples(block)?;
+        }
+        written += block.len();
+    }
+    writer.finish()?.sync_all()?;
+
+    let overs = meter.finish();
+    warn_overs("left", overs);
+    if stereo {
+        warn_overs("right", overs);
     }
+    Ok(())
+}
+
+/// Write one or two channels of samples to a 32bit .wav file.
+fn write_wav(filename: &str, chan_one: Vec<f32>, chan_two: Option<Vec<f32>>) {
+    let channels: Vec<&[f32]> = match chan_two {
+        Some(ref two) => vec![&chan_one, two],
+        None => vec![&chan_one],
+    };
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
 
+    let mut wav = WavWriter::new(File::create(filename).unwrap(), channels.len() as u16, 44100).unwrap();
+    for start in (0..len).step_by(BLOCK_SIZE) {
+        let end = (start + BLOCK_SIZE).min(len);
+        let blocks: Vec<&[f32]> = channels.iter().map(|c| &c[start..end]).collect();
+        wav.write_channels(&blocks).unwrap();
+    }
+    let _ = wav.finish().unwrap().sync_all();
 }
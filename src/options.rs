@@ -1,23 +1,382 @@
 extern crate getopts;
 
 use self::getopts::Options;
+pub use self::getopts::Matches;
 
-pub fn setup_options() -> Options {
-    let mut opts = Options::new();
-    
-    opts.reqopt("f", "frequency", "Frequency of generated tone", "FREQ")
-        .reqopt("l", "length", "Run length of generated wav.", "SECS")
+use dsp::delay::{Delay, Effect, ModulatedDelay, DEFAULT_CHORUS_DEPTH, DEFAULT_CHORUS_MIX, DEFAULT_DELAY_FEEDBACK,
+                 DEFAULT_DELAY_MIX, DEFAULT_FLANGER_DEPTH, DEFAULT_FLANGER_FEEDBACK, DEFAULT_FLANGER_MIX,
+                 parse_delay_time};
+use dsp::reverb::{Reverb, DEFAULT_REVERB_DAMPING, DEFAULT_REVERB_MIX};
+use dsp::stereo::{Imaging, Polarity};
+use synth::envelope::{Envelope, Curve};
+use synth::lfo::{Lfo, LfoShape, LfoTarget};
+use synth::pitch::{Tuning, load_scale, load_keymap};
+use synth::ksstring::{StringParams, Excitation, Retrigger, Follower,
+                      DEFAULT_RETRIGGER_DB, DEFAULT_FOLLOWER_WINDOW};
+
+/// Options the streaming path handles, a plain tone, waveform, pluck or patch with a --filter
+/// chain and the delay and reverb effects after it. Anything else given needs the whole signal.
+fn add_stream_options(opts: &mut Options) {
+    opts.optopt("f", "frequency",
+                "Frequency of generated tone, in Hz, as a note name like C#3 or a MIDI note like m61, \
+                 with cents like A4+15c. A list like 440,880@-6dB,1320@-12dB:90 sums partials with \
+                 level in dB and phase in degrees.", "FREQ")
+        .optopt("", "reference", "Frequency of A4 for note names, default 440, or of a --keymap's \
+                                  reference note.", "HZ")
+        .optopt("", "tuning", "Tuning for note names: 12tet (default), just, pythagorean or a Scala \
+                               .scl file.", "TUNING")
+        .optopt("", "keymap", "Scala .kbm keyboard mapping for the --tuning.", "FILE")
+        .optopt("l", "length", "Run length of generated wav.", "SECS")
         .reqopt("o", "out-file", "File name to write the wav file to", "FILE")
         .optflag("t", "tone", "Generate sine tone, default.")
+        .optopt("", "patch", "Render the node named out of a patch of oscillators, envelopes, \
+                             filters and effects connected in FILE, for --length seconds.", "FILE")
+        .optopt("", "waveform", "Tone waveform: sine, saw, square or triangle. Default sine.", "WAVE")
         .optflag("k", "karplus-strong", "Generate a karplus strong sample from single pluck.")
+        .optopt("", "decay-time", "Karplus-Strong decay to -60dB, replacing the fixed loss.", "SECS")
+        .optopt("", "brightness", "Karplus-Strong loop brightness, 0.0 (default) to 1.0.", "B")
+        .optopt("", "pick-position", "Karplus-Strong pluck point as a fraction of the string, \
+                                      0.0 to 1.0.", "P")
+        .optopt("", "dynamic-level", "Karplus-Strong pluck strength, softer is darker. 0.0 to 1.0, \
+                                      default 1.0.", "L")
+        .optopt("", "stiffness", "Karplus-Strong string stiffness, 0.0 (default) to 0.9.", "S")
+        .optopt("", "blend", "Karplus-Strong chance of keeping sign around the loop, 1.0 for a \
+                              string and 0.5 for a drum. Default 1.0, or 0.5 for the drum and snare.", "B")
+        .optopt("", "excitation", "Karplus-Strong pluck: noise (default), filtered[:HZ], impulse, \
+                                   pick or file:PATH to a .wav.", "SPEC")
+        .optopt("", "seed", "Seed the noise and random LFO shapes for repeatable output.", "N")
         .optflag("s", "stereo", "Make a stereo .wav file")
         .optflag("h", "help", "Print this help.")
+        .optopt("", "filter", "Filter the output through a chain of biquads, TYPE:FREQ[:Q[:GAIN]],... \
+                 Types lp, hp, bp, notch, ap, peak, lowshelf, highshelf, and bwlp, bwhp, lrlp, lrhp \
+                 taking an order in place of Q.", "CHAIN")
+        .optopt("", "delay", "Echo the output, TIME[:FEEDBACK[:MIX]] with time in seconds or a note \
+                 length at --tempo like 1/8, dotted 1/8d or triplet 1/8t, and feedback and mix 0.0 \
+                 to 1.0.", "SPEC")
+        .optopt("", "tempo", "Tempo for --delay note lengths, --bpm if not given.", "BPM")
+        .optopt("", "chorus", "Chorus, RATE[:DEPTH[:MIX]] with depth and mix 0.0 to 1.0.", "SPEC")
+        .optopt("", "flanger", "Flanger, RATE[:DEPTH[:FEEDBACK[:MIX]]] with feedback -1.0 to 1.0.",
+                "SPEC")
+        .optopt("", "reverb", "Reverb, SIZE[:DAMPING[:MIX]] all 0.0 to 1.0, default 0.5:0.5:0.3.",
+                "SPEC");
+}
+
+pub fn setup_options() -> Options {
+    let mut opts = Options::new();
+    add_stream_options(&mut opts);
+    opts.optopt("", "phases", "Phases for a multitone: given, zero, schroeder or newman.", "MODE")
+        .optopt("w", "sweep", "Generate a sine sweep from FREQ, TYPE is lin or exp (log).", "TYPE")
+        .optopt("", "end-frequency", "End frequency of a sweep or a karplus-strong --glide.", "FREQ")
+        .optopt("", "sweep-fade-in", "Half Hann fade in at the start of a sweep.", "SECS")
+        .optopt("", "sweep-fade-out", "Half Hann fade out at the end of a sweep.", "SECS")
+        .optopt("", "inverse-file", "Also write the inverse filter of a sweep to FILE.", "FILE")
+        .optopt("", "dtmf", "Generate DTMF tones for DIGITS, 0-9 * # A-D. '-' and ' ' are skipped.",
+                "DIGITS")
+        .optopt("", "sequence", "Play steps one after another, like '440Hz 0.5s, silence 0.2s, \
+                                 pluck E2 2s -6dB adsr=0.01:0.1:0.5:0.2'.", "STEPS")
+        .optopt("", "sequence-file", "Play the steps of a --sequence read from FILE.", "FILE")
+        .optopt("", "tone-length", "Length of each DTMF tone, default 0.1.", "SECS")
+        .optopt("", "gap", "Silence between DTMF tones, default 0.1.", "SECS")
+        .optopt("", "twist", "Level of DTMF high group relative to low group, default 0.", "DB")
+        .optopt("", "call-progress", "Generate a call progress tone: dial, busy, ringback or sit.",
+                "TONE")
+        .optopt("", "region", "Region for call progress tones: na, uk or eu. Default na.", "REGION")
+        .optopt("", "glide", "Slide the karplus-strong pitch to --end-frequency over SECS.", "SECS")
+        .optopt("", "percussion", "Generate a Karplus-Strong drum, snare or cymbal hit, tuned to \
+                                   FREQ.", "KIND")
+        .optflag("", "chord", "Play the -f frequencies as a chord of separate voices, plucked with -k. \
+                               Output is stereo.")
+        .optopt("", "strum", "Time between the notes of a --chord, low to high.", "SECS")
+        .optopt("", "spread", "Stereo spread of a --chord, 0.0 to 1.0. Default 0.6.", "WIDTH")
+        .optopt("", "voices", "Number of --chord voices, default one per note, or of each \
+                               instrument's voices for render, default 16.", "N")
+        .optopt("", "fm", "Generate a four operator FM tone from a preset: bell, epiano or woodblock.", "PRESET")
+        .optopt("", "algorithm", "Override the FM preset's operator algorithm, 1 to 8.", "N")
+        .optopt("", "additive", "Generate from partial tracks read from FILE.", "FILE")
+        .optflag("", "band-limit", "Fade out additive partials approaching Nyquist.")
+        .optflag("n", "noise", "Generate white noise.")
+        .optopt("", "left", "Generate the left channel from its own tone or pluck at FREQ, \
+                             --frequency if not given. Output is stereo.", "FREQ")
+        .optopt("", "right", "Generate the right channel from its own tone or pluck at FREQ.", "FREQ")
+        .optopt("", "phase-offset", "Start the right channel's tone DEGREES ahead of the left.",
+                "DEGREES")
+        .optopt("", "pan", "Pan a mono signal with equal power, or balance a stereo one, -1.0 (left) \
+                            to 1.0 (right). Give it as --pan=-0.5. Output is stereo.", "POS")
+        .optopt("", "width", "Stereo width, 0.0 for mono, 1.0 unchanged, above 1.0 wider.", "WIDTH")
+        .optopt("", "invert", "Invert the polarity of the left, right or both channels.", "CHANNELS")
+        .optopt("", "channel-delay", "Delay the right channel behind the left, or the left behind \
+                                      the right if negative, given as --channel-delay=-0.001.", "SECS")
+        .optopt("", "level", "Normalize to a peak level in dBFS, -1 or -1dBFS, an RMS level in \
+                              dBFS, -18rms, or an integrated loudness, -23LUFS. Give it as \
+                              --level=-18rms.", "LEVEL")
+        .optopt("", "limit", "Lookahead limit the true peak to DBTP, given as --limit=-1.", "DBTP")
+        .optopt("", "lookahead", "Lookahead of the --limit, default 0.005.", "SECS")
+        .optopt("", "soft-clip", "Soft clip samples to DBFS, given as --soft-clip=-0.5.", "DBFS")
         .optflagopt("r", "repeat",
-                    "Repeat the karplus-strong pluck when sample is below threshold", "THRESHOLD");
+                    "Repeat the karplus-strong pluck when the level falls below THRESHOLD dB, \
+                     default -40. Give it as --repeat=-30.", "THRESHOLD")
+        .optopt("", "follower", "Level measured for --repeat: rms or peak. Default rms.", "TYPE")
+        .optopt("", "window", "Window the --repeat level is measured over, default 0.05.", "SECS")
+        .optopt("", "bpm", "Repeat the karplus-strong pluck on every beat at BPM.", "BPM")
+        .optopt("", "onsets", "Pluck the karplus-strong string at each of a list of times.", "SECS,...")
+        .optopt("", "random-interval", "Repeat the karplus-strong pluck after random intervals, \
+                                        MIN:MAX.", "SECS")
+        .optopt("", "svf", "State variable filter, MODE:CUTOFF[:RESONANCE] with mode lp, hp, bp or \
+                 notch and resonance 0.0 to 1.0.", "SPEC")
+        .optopt("", "ladder", "Moog style ladder low pass, CUTOFF[:RESONANCE].", "SPEC")
+        .optopt("", "filter-env", "Sweep the --svf or --ladder cutoff up to OCTAVES with the ADSR \
+                 envelope, or a decay over the whole file if no ADSR is given.", "OCTAVES")
+        .optopt("", "attack", "Envelope attack time.", "SECS")
+        .optopt("", "decay", "Envelope decay time.", "SECS")
+        .optopt("", "sustain", "Envelope sustain level, 0.0 to 1.0.", "LEVEL")
+        .optopt("", "release", "Envelope release time, ends at the end of the file.", "SECS")
+        .optopt("", "curve", "Envelope segment curve, linear or exp. Default linear.", "CURVE")
+        .optopt("", "fade-in", "Linear fade in at the start of the file.", "SECS")
+        .optopt("", "fade-out", "Linear fade out at the end of the file.", "SECS")
+        .optopt("", "lfo-rate", "Enable an LFO at RATE.", "HZ")
+        .optopt("", "lfo-shape", "LFO shape: sine, triangle, square, sh or random. Default sine.",
+                "SHAPE")
+        .optopt("", "lfo-depth", "LFO depth, fraction of gain, semitones or fraction of decay rate \
+                 depending on target. Default 0.5.", "DEPTH")
+        .optopt("", "lfo-phase", "LFO starting phase.", "DEGREES")
+        .optopt("", "lfo-target", "LFO destination: amp (tremolo), freq (vibrato, sine tone only) or \
+                 damping (karplus-strong only) or cutoff or res (--svf or --ladder). Default amp.", "TARGET")
+        .optopt("", "am", "Amplitude modulate the output with a sine at FREQ.", "FREQ")
+        .optopt("", "am-depth", "Modulation depth for --am, 0.0 to 1.0. Default 1.0.", "DEPTH")
+        .optopt("", "ring-mod", "Ring modulate the output with a sine at FREQ.", "FREQ");
     opts
 }
 
+/// Whether the output can be streamed to the file a block at a time, every option in args
+/// being one the streaming path handles.
+pub fn can_stream(args: &[String]) -> bool {
+    let mut opts = Options::new();
+    add_stream_options(&mut opts);
+    opts.parse(args).is_ok_and(|m| m.free.is_empty())
+}
+
 pub fn print_help(opts: &Options, name: &str) {
-    let brief = format!("USE: {} [options]", name);
+    let brief = format!("USE: {} [options]\n     {} render FILE.mid [options]", name, name);
     print!("{}", opts.usage(&brief));
 }
+
+/// Parse an optional numeric parameter, panicking with the option name if it doesn't parse.
+pub fn opt_f64(matches: &Matches, name: &str) -> Option<f64> {
+    matches.opt_str(name).map(|s| {
+        s.parse().ok().unwrap_or_else(|| panic!("Error: {} parameter", name))
+    })
+}
+
+/// Parse the --seed option, panicking if it isn't a whole number.
+pub fn opt_seed(matches: &Matches) -> Option<u64> {
+    matches.opt_str("seed").map(|s| s.parse().expect("Error: seed parameter"))
+}
+
+/// Build the ADSR envelope requested on the command line, if any.
+///
+/// Missing stages default to an instant attack and decay, full sustain and no release. The
+/// release is placed so it finishes at the end of run_length.
+pub fn adsr_from_matches(matches: &Matches, run_length: f64) -> Option<Envelope> {
+    let stages = ["attack", "decay", "sustain", "release"];
+    if !stages.iter().any(|s| matches.opt_present(s)) {
+        return None;
+    }
+    let curve = match matches.opt_str("curve") {
+        Some(c) => Curve::from_name(&c).expect("Error: curve parameter"),
+        None => Curve::Linear,
+    };
+    let attack = opt_f64(matches, "attack").unwrap_or(0.0);
+    let release = opt_f64(matches, "release").unwrap_or(0.0).min(run_length);
+    let gate = run_length - release;
+
+    if !matches.opt_present("decay") && !matches.opt_present("sustain") {
+        return Some(Envelope::ar(attack, release, gate, curve));
+    }
+    let decay = opt_f64(matches, "decay").unwrap_or(0.0);
+    let sustain = opt_f64(matches, "sustain").unwrap_or(1.0);
+
+    Some(Envelope::adsr(attack, decay, sustain as f32, release, gate, curve))
+}
+
+/// Build the fade in/out envelope requested on the command line, if any.
+pub fn fade_from_matches(matches: &Matches, run_length: f64) -> Option<Envelope> {
+    if !matches.opt_present("fade-in") && !matches.opt_present("fade-out") {
+        return None;
+    }
+    let fade_in = opt_f64(matches, "fade-in").unwrap_or(0.0);
+    let fade_out = opt_f64(matches, "fade-out").unwrap_or(0.0);
+
+    Some(Envelope::fade(fade_in, fade_out, run_length))
+}
+
+/// Tuning for note names from the command line, 12 tone equal temperament on A4 = 440 if none
+/// is given.
+pub fn tuning_from_matches(matches: &Matches) -> Result<Tuning, String> {
+    let mut tuning = match matches.opt_str("tuning") {
+        Some(name) => match Tuning::from_name(&name) {
+            Some(t) => t,
+            None => load_scale(&name)?,
+        },
+        None => Tuning::equal(),
+    };
+    if let Some(path) = matches.opt_str("keymap") {
+        tuning = load_keymap(tuning, &path)?;
+    }
+    if let Some(reference) = opt_f64(matches, "reference") {
+        tuning.set_reference_frequency(reference);
+    }
+    Ok(tuning)
+}
+
+/// Stereo imaging from the command line, if any is asked for.
+pub fn imaging_from_matches(matches: &Matches) -> Result<Option<Imaging>, String> {
+    if !["pan", "width", "invert", "channel-delay"].iter().any(|o| matches.opt_present(o)) {
+        return Ok(None);
+    }
+    let polarity = match matches.opt_str("invert") {
+        Some(c) => Polarity::from_name(&c).ok_or_else(|| format!("Unknown channels '{}'", c))?,
+        None => Polarity::Normal,
+    };
+
+    Ok(Some(Imaging {
+        pan: opt_f64(matches, "pan").map(|p| p as f32),
+        width: opt_f64(matches, "width").map_or(1.0, |w| w as f32),
+        polarity,
+        delay: opt_f64(matches, "channel-delay").unwrap_or(0.0),
+    }))
+}
+
+/// Delay, chorus, flanger and reverb from the command line, in the order they are run.
+pub fn effects_from_matches(matches: &Matches, sample_rate: f64) -> Result<Vec<Box<dyn Effect>>, String> {
+    // Settings of an effect after the first, defaults for any not given.
+    fn settings(name: &str, fields: &[&str], defaults: &[f64]) -> Result<Vec<f64>, String> {
+        if fields.len() > defaults.len() + 1 {
+            return Err(format!("{} parameter, too many settings", name));
+        }
+        defaults.iter().enumerate().map(|(i, d)| match fields.get(i + 1) {
+            Some(f) => f.parse().map_err(|_| format!("{} parameter, bad number '{}'", name, f)),
+            None => Ok(*d),
+        }).collect()
+    }
+    let first = |name: &str, field: &str| -> Result<f64, String> {
+        field.parse().map_err(|_| format!("{} parameter, bad number '{}'", name, field))
+    };
+
+    let mut effects: Vec<Box<dyn Effect>> = Vec::new();
+    if let Some(spec) = matches.opt_str("delay") {
+        let fields: Vec<&str> = spec.split(':').collect();
+        let tempo = matches.opt_str("tempo").or_else(|| matches.opt_str("bpm"))
+                           .map(|t| first("tempo", &t)).transpose()?;
+        let time = parse_delay_time(fields[0], tempo).map_err(|e| format!("delay parameter, {}", e))?;
+        let s = settings("delay", &fields, &[DEFAULT_DELAY_FEEDBACK as f64, DEFAULT_DELAY_MIX as f64])?;
+        effects.push(Box::new(Delay::new(time, s[0] as f32, s[1] as f32, sample_rate)));
+    }
+    if let Some(spec) = matches.opt_str("chorus") {
+        let fields: Vec<&str> = spec.split(':').collect();
+        let s = settings("chorus", &fields, &[DEFAULT_CHORUS_DEPTH, DEFAULT_CHORUS_MIX as f64])?;
+        effects.push(Box::new(ModulatedDelay::chorus(first("chorus", fields[0])?, s[0], s[1] as f32, sample_rate)));
+    }
+    if let Some(spec) = matches.opt_str("flanger") {
+        let fields: Vec<&str> = spec.split(':').collect();
+        let s = settings("flanger", &fields, &[DEFAULT_FLANGER_DEPTH, DEFAULT_FLANGER_FEEDBACK as f64,
+                                                 DEFAULT_FLANGER_MIX as f64])?;
+        effects.push(Box::new(ModulatedDelay::flanger(first("flanger", fields[0])?, s[0], s[1] as f32, s[2] as f32,
+                                                      sample_rate)));
+    }
+    if let Some(spec) = matches.opt_str("reverb") {
+        let fields: Vec<&str> = spec.split(':').collect();
+        let size = first("reverb", fields[0])?;
+        let s = settings("reverb", &fields, &[DEFAULT_REVERB_DAMPING as f64, DEFAULT_REVERB_MIX as f64])?;
+        effects.push(Box::new(Reverb::new(size as f32, s[0] as f32, s[1] as f32, sample_rate)));
+    }
+    Ok(effects)
+}
+
+/// Karplus-Strong string parameters from the command line, defaults for any not given.
+pub fn string_from_matches(matches: &Matches) -> Result<StringParams, String> {
+    let default = StringParams::default();
+    let opt_f32 = |name: &str, default: f32| opt_f64(matches, name).map_or(default, |v| v as f32);
+
+    let excitation = match matches.opt_str("excitation") {
+        Some(spec) => Excitation::from_spec(&spec)?,
+        None => default.excitation,
+    };
+
+    Ok(StringParams {
+        decay_time: opt_f64(matches, "decay-time"),
+        brightness: opt_f32("brightness", default.brightness),
+        pick_position: opt_f32("pick-position", default.pick_position),
+        dynamic_level: opt_f32("dynamic-level", default.dynamic_level),
+        stiffness: opt_f32("stiffness", default.stiffness),
+        blend: opt_f32("blend", default.blend),
+        excitation,
+        seed: opt_seed(matches),
+    })
+}
+
+/// Retrigger schedule for the Karplus-Strong string from the command line, if any.
+pub fn retrigger_from_matches(matches: &Matches) -> Result<Option<Retrigger>, String> {
+    let positive = |name: &str, s: &str| -> Result<f64, String> {
+        match s.trim().parse::<f64>() {
+            Ok(v) if v > 0.0 => Ok(v),
+            _ => Err(format!("Bad {} '{}'", name, s)),
+        }
+    };
+
+    if let Some(bpm) = matches.opt_str("bpm") {
+        return positive("tempo", &bpm).map(|b| Some(Retrigger::Bpm(b)));
+    }
+    if let Some(list) = matches.opt_str("onsets") {
+        let times = list.split(',')
+                        .map(|t| t.trim().parse().map_err(|_| format!("Bad onset time '{}'", t)))
+                        .collect::<Result<Vec<f64>, String>>()?;
+        return Ok(Some(Retrigger::Onsets(times)));
+    }
+    if let Some(spec) = matches.opt_str("random-interval") {
+        let mut parts = spec.splitn(2, ':');
+        let shortest = positive("interval", parts.next().unwrap_or(""))?;
+        let longest = match parts.next() {
+            Some(l) => positive("interval", l)?,
+            None => shortest,
+        };
+        return Ok(Some(Retrigger::Random(shortest.min(longest), shortest.max(longest))));
+    }
+    if !matches.opt_present("r") {
+        return Ok(None);
+    }
+
+    let level_db = match matches.opt_str("r") {
+        Some(t) => match t.parse::<f64>() {
+            Ok(db) if db <= 0.0 => db,
+            _ => return Err(format!("Threshold '{}' must be in dB, 0 or below", t)),
+        },
+        None => DEFAULT_RETRIGGER_DB,
+    };
+    let follower = match matches.opt_str("follower") {
+        Some(f) => Follower::from_name(&f).ok_or_else(|| format!("Unknown follower '{}'", f))?,
+        None => Follower::Rms,
+    };
+    let window = match matches.opt_str("window") {
+        Some(w) => positive("window", &w)?,
+        None => DEFAULT_FOLLOWER_WINDOW,
+    };
+
+    Ok(Some(Retrigger::Threshold { level_db, window, follower }))
+}
+
+/// Build the LFO requested on the command line and its destination, if any.
+pub fn lfo_from_matches(matches: &Matches, sample_rate: f64) -> Option<(Lfo, LfoTarget)> {
+    let rate = opt_f64(matches, "lfo-rate")?;
+    let shape = match matches.opt_str("lfo-shape") {
+        Some(s) => LfoShape::from_name(&s).expect("Error: lfo-shape parameter"),
+        None => LfoShape::Sine,
+    };
+    let target = match matches.opt_str("lfo-target") {
+        Some(t) => LfoTarget::from_name(&t).expect("Error: lfo-target parameter"),
+        None => LfoTarget::Amplitude,
+    };
+    let depth = opt_f64(matches, "lfo-depth").unwrap_or(0.5) as f32;
+    let phase = opt_f64(matches, "lfo-phase").unwrap_or(0.0);
+
+    Some((Lfo::new(shape, rate, depth, opt_seed(matches), sample_rate).with_phase(phase), target))
+}
@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io::Read;
+
+use synth::envelope::{Envelope, Curve};
+use synth::oscillator::SineOscillator;
+
+/// One partial of an additive voice, frequency and amplitude given as breakpoints over time.
+#[derive(Debug, Clone)]
+pub struct PartialTrack {
+    pub frequency: Envelope,
+    /// Linear amplitude.
+    pub amplitude: Envelope,
+}
+
+impl PartialTrack {
+    pub fn new() -> PartialTrack {
+        PartialTrack { frequency: Envelope::new(), amplitude: Envelope::new() }
+    }
+
+    /// Add a breakpoint to both frequency and amplitude at time seconds.
+    pub fn add_point(&mut self, time: f64, frequency: f64, amplitude: f32) {
+        self.frequency.add_point(time, frequency as f32, Curve::Linear);
+        self.amplitude.add_point(time, amplitude, Curve::Linear);
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frequency.duration().max(self.amplitude.duration())
+    }
+}
+
+/// Parse partial tracks from text.
+///
+/// Each track starts with a line reading `partial`, followed by one `time frequency amplitude`
+/// line per breakpoint, in time order. Blank lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// partial
+/// 0.0  440.0 0.0
+/// 0.05 440.0 0.5
+/// 2.0  438.0 0.0
+/// ```
+pub fn parse_partial_tracks(text: &str) -> Result<Vec<PartialTrack>, String> {
+    let mut tracks: Vec<PartialTrack> = Vec::new();
+    let mut last_time: f64 = 0.0;
+    // Line of the last `partial` and the breakpoints seen since.
+    let mut header: usize = 0;
+    let mut breakpoints: usize = 0;
+    let no_breakpoints = |header: usize| format!("line {}: partial with no breakpoints", header);
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "partial" {
+            if !tracks.is_empty() && breakpoints == 0 {
+                return Err(no_breakpoints(header));
+            }
+            tracks.push(PartialTrack::new());
+            last_time = 0.0;
+            header = n + 1;
+            breakpoints = 0;
+            continue;
+        }
+
+        let fields: Vec<f64> = line.split_whitespace().map(|f| f.parse::<f64>()).collect::<Result<_, _>>()
+            .map_err(|_| format!("line {}: expected numbers", n + 1))?;
+        if fields.len() != 3 {
+            return Err(format!("line {}: expected time frequency amplitude", n + 1));
+        }
+        if fields[0] < last_time {
+            return Err(format!("line {}: breakpoints out of time order", n + 1));
+        }
+        last_time = fields[0];
+        match tracks.last_mut() {
+            Some(t) => t.add_point(fields[0], fields[1], fields[2] as f32),
+            None => return Err(format!("line {}: breakpoint before first 'partial'", n + 1)),
+        }
+        breakpoints += 1;
+    }
+    if !tracks.is_empty() && breakpoints == 0 {
+        return Err(no_breakpoints(header));
+    }
+
+    Ok(tracks)
+}
+
+/// Read and parse a partial track file.
+pub fn load_partial_tracks(path: &str) -> Result<Vec<PartialTrack>, String> {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+    parse_partial_tracks(&text)
+}
+
+/// Render partial tracks for run_length seconds, each with its own phase continuous oscillator.
+///
+/// With band_limit set, partials are faded out as they approach the Nyquist frequency and are
+/// silent above it, instead of aliasing.
+pub fn generate_additive(run_length: f64, tracks: &[PartialTrack], band_limit: bool, sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let nyquist: f32 = (sample_rate / 2.0) as f32;
+    // Band limited partials start fading this far below Nyquist.
+    let fade_start: f32 = nyquist * 0.9;
+
+    let mut out_vec: Vec<f32> = vec![0.0; num_samples];
+
+    for track in tracks {
+        let freqs = track.frequency.render(num_samples, sample_rate);
+        let amps = track.amplitude.render(num_samples, sample_rate);
+        let mut osc = SineOscillator::new(sample_rate);
+
+        for i in 0..num_samples {
+            let f = freqs[i];
+            let mut a = amps[i];
+            if band_limit && f > fade_start {
+                a *= ((nyquist - f) / (nyquist - fade_start)).max(0.0);
+            }
+            out_vec[i] += osc.tick(f as f64) * a;
+        }
+    }
+
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_render_tracks() {
+        let text = "# two partials\npartial\n0.0 100 0.0\n0.5 100 0.5\npartial\n0 23000 0.5\n1 23000 0.5\n";
+        let tracks = parse_partial_tracks(text).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].duration(), 0.5);
+
+        // The second partial is above Nyquist so band limiting leaves only the first.
+        let out = generate_additive(1.0, &tracks, true, 44100.0);
+        let peak = out.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak > 0.49 && peak <= 0.5);
+
+        assert!(parse_partial_tracks("0 100 1.0").is_err());
+        assert!(parse_partial_tracks("partial\n1 100 1\n0 100 1").is_err());
+    }
+
+    #[test]
+    fn tracks_need_breakpoints() {
+        // An empty track would render as a full scale partial, its envelopes holding at 1.0.
+        assert_eq!(parse_partial_tracks("partial\n0 100 0.5\npartial\n").unwrap_err(),
+                   "line 3: partial with no breakpoints");
+        assert_eq!(parse_partial_tracks("partial\n# nothing yet\npartial\n0 100 0.5").unwrap_err(),
+                   "line 1: partial with no breakpoints");
+        assert_eq!(parse_partial_tracks("").map(|t| t.len()), Ok(0));
+    }
+}
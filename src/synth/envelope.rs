@@ -0,0 +1,187 @@
+/// Shape of the segment leading into a breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// RC style curve, moves quickly at the start of the segment then settles into the target.
+    Exponential,
+}
+
+impl Curve {
+    /// Parse a curve name as given on the command line.
+    pub fn from_name(name: &str) -> Option<Curve> {
+        match name {
+            "lin" | "linear" => Some(Curve::Linear),
+            "exp" | "exponential" => Some(Curve::Exponential),
+            _ => None,
+        }
+    }
+
+    /// Interpolate between a and b, x being the position in the segment from 0.0 to 1.0.
+    fn interpolate(&self, a: f32, b: f32, x: f64) -> f32 {
+        let shaped: f64 = match *self {
+            Curve::Linear => x,
+            Curve::Exponential => {
+                (1.0f64 - (-EXP_CURVATURE * x).exp()) / (1.0f64 - (-EXP_CURVATURE).exp())
+            }
+        };
+        a + (b - a) * shaped as f32
+    }
+}
+
+// Time constants that fit in one segment of an exponential curve.
+const EXP_CURVATURE: f64 = 5.0;
+
+/// A single point of an envelope, level is reached at time in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Breakpoint {
+    pub time: f64,
+    pub level: f32,
+    pub curve: Curve,
+}
+
+/// Multi-segment envelope built from breakpoints sorted by time.
+///
+/// Before the first point the first level is held, after the last point the last level is held.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    points: Vec<Breakpoint>,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope { points: Vec::new() }
+    }
+
+    /// Append a breakpoint. Points must be added in time order.
+    pub fn add_point(&mut self, time: f64, level: f32, curve: Curve) {
+        if let Some(last) = self.points.last() {
+            assert!(time >= last.time, "Envelope breakpoints must be in time order.");
+        }
+        self.points.push(Breakpoint { time, level, curve });
+    }
+
+    /// Attack, decay, sustain envelope, held until release() is used to end it.
+    pub fn ads(attack: f64, decay: f64, sustain: f32, curve: Curve) -> Envelope {
+        let mut env = Envelope::new();
+        env.add_point(0.0, 0.0, Curve::Linear);
+        env.add_point(attack, 1.0, curve);
+        env.add_point(attack + decay, sustain, curve);
+        env
+    }
+
+    /// Classic ADSR where the gate is held for gate_length seconds, release follows the gate.
+    pub fn adsr(attack: f64, decay: f64, sustain: f32, release: f64, gate_length: f64, curve: Curve) -> Envelope {
+        let mut env = Envelope::ads(attack, decay, sustain, curve);
+        env.release(gate_length, release, curve);
+        env
+    }
+
+    /// Attack then release, with release starting at gate_length seconds.
+    pub fn ar(attack: f64, release: f64, gate_length: f64, curve: Curve) -> Envelope {
+        let mut env = Envelope::new();
+        env.add_point(0.0, 0.0, Curve::Linear);
+        env.add_point(attack, 1.0, curve);
+        env.release(gate_length, release, curve);
+        env
+    }
+
+    /// Linear fade in and fade out over a run_length seconds long signal.
+    pub fn fade(fade_in: f64, fade_out: f64, run_length: f64) -> Envelope {
+        let mut env = Envelope::new();
+        let fade_in = fade_in.min(run_length);
+        let fade_out = fade_out.min(run_length - fade_in);
+        env.add_point(0.0, if fade_in > 0.0 { 0.0 } else { 1.0 }, Curve::Linear);
+        env.add_point(fade_in, 1.0, Curve::Linear);
+        env.add_point(run_length - fade_out, 1.0, Curve::Linear);
+        env.add_point(run_length, if fade_out > 0.0 { 0.0 } else { 1.0 }, Curve::Linear);
+        env
+    }
+
+    /// Cut the envelope at gate_time, then fall to zero over release seconds.
+    ///
+    /// The release starts from whatever level the envelope had reached at gate_time, so a gate
+    /// shorter than the attack releases from the partial attack level.
+    pub fn release(&mut self, gate_time: f64, release: f64, curve: Curve) {
+        let gate_level = self.level_at(gate_time);
+        self.points.retain(|p| p.time < gate_time);
+        self.add_point(gate_time, gate_level, Curve::Linear);
+        self.add_point(gate_time + release, 0.0, curve);
+    }
+
+    /// Time of the last breakpoint, after which the level holds.
+    pub fn duration(&self) -> f64 {
+        match self.points.last() {
+            Some(p) => p.time,
+            None => 0.0,
+        }
+    }
+
+    /// Envelope level at time in seconds.
+    pub fn level_at(&self, time: f64) -> f32 {
+        if self.points.is_empty() {
+            return 1.0;
+        }
+        let seg = self.points.iter().take_while(|p| p.time <= time).count();
+        self.segment_level(seg, time)
+    }
+
+    /// Multiply samples by the envelope, sample 0 being time 0.0.
+    pub fn apply(&self, samples: &mut [f32], sample_rate: f64) {
+        if self.points.is_empty() {
+            return;
+        }
+        // Walk segments alongside the samples instead of searching per sample.
+        let mut seg: usize = 0;
+        for (i, s) in samples.iter_mut().enumerate() {
+            let t: f64 = i as f64 / sample_rate;
+            while seg < self.points.len() && self.points[seg].time <= t {
+                seg += 1;
+            }
+            *s *= self.segment_level(seg, t);
+        }
+    }
+
+    /// Envelope levels for num_samples samples, for use as a per sample control signal.
+    pub fn render(&self, num_samples: usize, sample_rate: f64) -> Vec<f32> {
+        let mut levels: Vec<f32> = vec![1.0; num_samples];
+        self.apply(&mut levels, sample_rate);
+        levels
+    }
+
+    // seg is the count of points at or before time, so the segment runs from seg - 1 to seg.
+    fn segment_level(&self, seg: usize, time: f64) -> f32 {
+        if seg == 0 {
+            self.points[0].level
+        } else if seg >= self.points.len() {
+            self.points[self.points.len() - 1].level
+        } else {
+            let a = &self.points[seg - 1];
+            let b = &self.points[seg];
+            b.curve.interpolate(a.level, b.level, (time - a.time) / (b.time - a.time))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adsr_levels() {
+        let env = Envelope::adsr(0.1, 0.1, 0.5, 0.2, 1.0, Curve::Linear);
+        assert_eq!(env.level_at(0.0), 0.0);
+        assert!((env.level_at(0.05) - 0.5).abs() < 1e-6);
+        assert!((env.level_at(0.1) - 1.0).abs() < 1e-6);
+        assert!((env.level_at(0.5) - 0.5).abs() < 1e-6);
+        assert!((env.level_at(1.1) - 0.25).abs() < 1e-6);
+        assert_eq!(env.level_at(2.0), 0.0);
+    }
+
+    #[test]
+    fn release_before_attack_ends() {
+        let env = Envelope::ar(1.0, 1.0, 0.5, Curve::Linear);
+        assert!((env.level_at(0.5) - 0.5).abs() < 1e-6);
+        assert!((env.level_at(1.0) - 0.25).abs() < 1e-6);
+        assert_eq!(env.level_at(1.5), 0.0);
+    }
+}
@@ -0,0 +1,250 @@
+use synth::envelope::{Envelope, Curve};
+use synth::oscillator::SineOscillator;
+
+/// How an operator's frequency is derived from the note frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperatorFrequency {
+    /// Multiple of the note frequency.
+    Ratio(f64),
+    /// Fixed frequency in Hz regardless of note.
+    Fixed(f64),
+}
+
+/// A sine operator of an FM voice.
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub frequency: OperatorFrequency,
+    /// Amplitude when used as a carrier, peak modulation index in radians as a modulator.
+    pub level: f32,
+    /// Amount of the operator's own output fed back to its phase, in radians.
+    pub feedback: f32,
+    pub envelope: Envelope,
+}
+
+impl Operator {
+    pub fn new(frequency: OperatorFrequency, level: f32, envelope: Envelope) -> Operator {
+        Operator { frequency, level, feedback: 0.0, envelope }
+    }
+
+    pub fn with_feedback(mut self, feedback: f32) -> Operator {
+        self.feedback = feedback;
+        self
+    }
+}
+
+/// Routing of operators, the numbered ones being the DX9/TX81Z four operator algorithms.
+///
+/// Operators are numbered from 1. Modulators always have a higher number than the operator they
+/// modulate, so rendering from the highest down to 1 has every input ready.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Algorithm {
+    /// (modulator, target) pairs.
+    pub connections: &'static [(usize, usize)],
+    /// Operators summed to the output.
+    pub carriers: &'static [usize],
+}
+
+const ALGORITHMS: [Algorithm; 8] = [
+    Algorithm { connections: &[(4, 3), (3, 2), (2, 1)], carriers: &[1] },
+    Algorithm { connections: &[(4, 2), (3, 2), (2, 1)], carriers: &[1] },
+    Algorithm { connections: &[(4, 1), (3, 2), (2, 1)], carriers: &[1] },
+    Algorithm { connections: &[(4, 3), (3, 1), (2, 1)], carriers: &[1] },
+    Algorithm { connections: &[(4, 3), (2, 1)], carriers: &[1, 3] },
+    Algorithm { connections: &[(4, 1), (4, 2), (4, 3)], carriers: &[1, 2, 3] },
+    Algorithm { connections: &[(4, 3)], carriers: &[1, 2, 3] },
+    Algorithm { connections: &[], carriers: &[1, 2, 3, 4] },
+];
+
+impl Algorithm {
+    /// Algorithm by number, 1 to 8.
+    pub fn number(n: usize) -> Option<Algorithm> {
+        if n >= 1 && n <= ALGORITHMS.len() {
+            Some(ALGORITHMS[n - 1])
+        } else {
+            None
+        }
+    }
+
+    /// Whether the routing only names operators 1 to operators, each modulator above its target.
+    pub fn fits(&self, operators: usize) -> bool {
+        let named = |n: usize| n >= 1 && n <= operators;
+        self.connections.iter().all(|&(m, t)| named(m) && named(t) && m > t) &&
+            self.carriers.iter().all(|c| named(*c))
+    }
+}
+
+/// FM voice of any number of operators, rendered with phase modulation as on the DX series.
+pub struct FmVoice {
+    operators: Vec<Operator>,
+    algorithm: Algorithm,
+    oscs: Vec<SineOscillator>,
+    // Last two outputs per operator, averaged for feedback.
+    last_out: Vec<[f32; 2]>,
+    // Output of each operator for the sample being rendered.
+    outs: Vec<f32>,
+    sample_rate: f64,
+    ticks: u64,
+}
+
+impl FmVoice {
+    pub fn new(operators: Vec<Operator>, algorithm: Algorithm, sample_rate: f64) -> FmVoice {
+        assert!(algorithm.fits(operators.len()), "Algorithm doesn't fit the operators.");
+        FmVoice {
+            oscs: vec![SineOscillator::new(sample_rate); operators.len()],
+            last_out: vec![[0.0; 2]; operators.len()],
+            outs: vec![0.0; operators.len()],
+            operators,
+            algorithm,
+            sample_rate,
+            ticks: 0,
+        }
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        assert!(algorithm.fits(self.operators.len()), "Algorithm doesn't fit the operators.");
+        self.algorithm = algorithm;
+    }
+
+    /// Render the next sample at note frequency.
+    pub fn tick(&mut self, frequency: f64) -> f32 {
+        let time = self.ticks as f64 / self.sample_rate;
+        for n in (1..=self.operators.len()).rev() {
+            let i = n - 1;
+            let op = &self.operators[i];
+            let op_freq = match op.frequency {
+                OperatorFrequency::Ratio(r) => frequency * r,
+                OperatorFrequency::Fixed(f) => f,
+            };
+            let mut phase_mod: f64 = self.algorithm.connections.iter()
+                .filter(|c| c.1 == n)
+                .map(|c| self.outs[c.0 - 1] as f64)
+                .sum();
+            let fb = &self.last_out[i];
+            phase_mod += (op.feedback * (fb[0] + fb[1]) * 0.5) as f64;
+
+            let out = self.oscs[i].tick_pm(op_freq, phase_mod) * op.level * op.envelope.level_at(time);
+            self.last_out[i] = [out, fb[0]];
+            self.outs[i] = out;
+        }
+        self.ticks += 1;
+
+        let carriers = self.algorithm.carriers;
+        carriers.iter().map(|c| self.outs[c - 1]).sum::<f32>() / carriers.len() as f32
+    }
+}
+
+/// Tubular bell, two inharmonic carrier/modulator pairs with long exponential decays.
+pub fn bell_preset(run_length: f64, sample_rate: f64) -> FmVoice {
+    let env = |decay: f64| Envelope::adsr(0.002, decay, 0.0, 0.0, run_length, Curve::Exponential);
+    let ops = vec![
+        Operator::new(OperatorFrequency::Ratio(1.0), 1.0, env(run_length)),
+        Operator::new(OperatorFrequency::Ratio(3.5), 4.0, env(run_length * 0.6)),
+        Operator::new(OperatorFrequency::Ratio(2.76), 0.5, env(run_length * 0.4)),
+        Operator::new(OperatorFrequency::Ratio(1.41), 2.5, env(run_length * 0.3)),
+    ];
+    FmVoice::new(ops, Algorithm::number(5).unwrap(), sample_rate)
+}
+
+/// Electric piano, a soft body pair and a high ratio pair for the tine attack.
+pub fn electric_piano_preset(run_length: f64, sample_rate: f64) -> FmVoice {
+    let release = 0.3f64.min(run_length);
+    let gate = run_length - release;
+    let env = |decay: f64, sustain: f32| {
+        Envelope::adsr(0.002, decay, sustain, release, gate, Curve::Exponential)
+    };
+    let ops = vec![
+        Operator::new(OperatorFrequency::Ratio(1.0), 1.0, env(3.0, 0.0)),
+        Operator::new(OperatorFrequency::Ratio(1.0), 1.2, env(1.5, 0.2)),
+        Operator::new(OperatorFrequency::Ratio(1.0), 0.4, env(0.8, 0.0)),
+        Operator::new(OperatorFrequency::Ratio(14.0), 1.5, env(0.15, 0.0)).with_feedback(0.3),
+    ];
+    FmVoice::new(ops, Algorithm::number(5).unwrap(), sample_rate)
+}
+
+/// Woodblock, short decays with a fixed frequency modulator giving the same knock at any pitch.
+pub fn woodblock_preset(run_length: f64, sample_rate: f64) -> FmVoice {
+    let env = |decay: f64| Envelope::adsr(0.0005, decay, 0.0, 0.0, run_length, Curve::Exponential);
+    let ops = vec![
+        Operator::new(OperatorFrequency::Ratio(1.0), 1.0, env(0.25)),
+        Operator::new(OperatorFrequency::Fixed(1720.0), 1.8, env(0.03)),
+        Operator::new(OperatorFrequency::Ratio(2.49), 0.3, env(0.08)),
+        Operator::new(OperatorFrequency::Ratio(5.1), 0.6, env(0.02)),
+    ];
+    FmVoice::new(ops, Algorithm::number(5).unwrap(), sample_rate)
+}
+
+/// Look up an FM preset by the name given on the command line.
+pub fn preset_from_name(name: &str, run_length: f64, sample_rate: f64) -> Option<FmVoice> {
+    match name {
+        "bell" => Some(bell_preset(run_length, sample_rate)),
+        "epiano" | "electric-piano" => Some(electric_piano_preset(run_length, sample_rate)),
+        "woodblock" => Some(woodblock_preset(run_length, sample_rate)),
+        _ => None,
+    }
+}
+
+/// Render an FM voice at frequency for run_length seconds.
+pub fn generate_fm(run_length: f64, frequency: f64, voice: &mut FmVoice, sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+
+    for _ in 0..num_samples {
+        out_vec.push(voice.tick(frequency));
+    }
+
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithm_eight_is_additive() {
+        let flat = || Envelope::ads(0.0, 0.0, 1.0, Curve::Linear);
+        let ops = (0..4).map(|_| Operator::new(OperatorFrequency::Ratio(1.0), 1.0, flat())).collect();
+        let mut voice = FmVoice::new(ops, Algorithm::number(8).unwrap(), 44100.0);
+        let fm = generate_fm(0.01, 440.0, &mut voice, 44100.0);
+
+        let mut osc = SineOscillator::new(44100.0);
+        for s in fm.iter() {
+            assert!((s - osc.tick(440.0)).abs() < 1e-5);
+        }
+    }
+
+    // Amplitude of the component at a whole number frequency in one second of samples.
+    fn level_at(samples: &[f32], frequency: f64) -> f64 {
+        let w = 2.0 * ::std::f64::consts::PI * frequency / samples.len() as f64;
+        let (re, im) = samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, s)| {
+            (re + *s as f64 * (w * n as f64).cos(), im - *s as f64 * (w * n as f64).sin())
+        });
+        2.0 * re.hypot(im) / samples.len() as f64
+    }
+
+    #[test]
+    fn modulation_makes_sidebands() {
+        let flat = || Envelope::ads(0.0, 0.0, 1.0, Curve::Linear);
+        let op = |ratio: f64, level: f32| Operator::new(OperatorFrequency::Ratio(ratio), level, flat());
+
+        // Algorithm 5, operator 2 modulating 1 at index 1.0 puts sidebands 110Hz either side of
+        // the carrier, at J1(1.0) = 0.44 of its level halved between the two carriers.
+        let ops = vec![op(1.0, 1.0), op(0.25, 1.0), op(1.0, 0.0), op(1.0, 0.0)];
+        let fm = generate_fm(1.0, 440.0, &mut FmVoice::new(ops, Algorithm::number(5).unwrap(), 44100.0), 44100.0);
+        for f in [330.0, 550.0].iter() {
+            assert!((level_at(&fm, *f) - 0.22).abs() < 0.01, "{}Hz", f);
+        }
+        assert!((level_at(&fm, 440.0) - 0.383).abs() < 0.01);
+
+        // Feedback on the carrier of algorithm 1 alone adds harmonics.
+        let ops = vec![op(1.0, 1.0).with_feedback(1.0), op(1.0, 0.0), op(1.0, 0.0), op(1.0, 0.0)];
+        let fm = generate_fm(1.0, 440.0, &mut FmVoice::new(ops, Algorithm::number(1).unwrap(), 44100.0), 44100.0);
+        assert!(level_at(&fm, 880.0) > 0.1);
+
+        // Any number of operators, here a two operator stack.
+        let pair = Algorithm { connections: &[(2, 1)], carriers: &[1] };
+        let fm = generate_fm(1.0, 440.0, &mut FmVoice::new(vec![op(1.0, 1.0), op(0.25, 1.0)], pair, 44100.0),
+                             44100.0);
+        assert!((level_at(&fm, 330.0) - 0.44).abs() < 0.01);
+        assert!(!Algorithm::number(1).unwrap().fits(3));
+    }
+}
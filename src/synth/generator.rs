@@ -0,0 +1,111 @@
+/// Source of samples that can be rendered a block at a time.
+///
+/// Rendering in blocks lets a signal of any length pass through effects and into a writer
+/// without the whole of it being held in memory.
+pub trait Generator {
+    /// Output the next sample.
+    fn tick(&mut self) -> f32;
+
+    /// Fill out with the next samples.
+    fn process(&mut self, out: &mut [f32]) {
+        for s in out.iter_mut() {
+            *s = self.tick();
+        }
+    }
+
+    /// Pass every block through processor once it has been generated, for filters and other
+    /// effects working in place on a buffer.
+    fn chain<P: FnMut(&mut [f32])>(self, processor: P) -> Chain<Self, P> where Self: Sized {
+        Chain { source: self, processor }
+    }
+
+    /// The samples as an endless iterator.
+    fn samples(self) -> Samples<Self> where Self: Sized {
+        Samples { source: self }
+    }
+}
+
+impl<G: Generator + ?Sized> Generator for Box<G> {
+    fn tick(&mut self) -> f32 {
+        (**self).tick()
+    }
+
+    fn process(&mut self, out: &mut [f32]) {
+        (**self).process(out)
+    }
+}
+
+/// A generator followed by an effect, from Generator::chain().
+pub struct Chain<G, P> {
+    source: G,
+    processor: P,
+}
+
+impl<G: Generator, P: FnMut(&mut [f32])> Generator for Chain<G, P> {
+    fn tick(&mut self) -> f32 {
+        let mut one = [self.source.tick()];
+        (self.processor)(&mut one);
+        one[0]
+    }
+
+    fn process(&mut self, out: &mut [f32]) {
+        self.source.process(out);
+        (self.processor)(out);
+    }
+}
+
+/// Iterator over the samples of a generator, from Generator::samples().
+pub struct Samples<G> {
+    source: G,
+}
+
+impl<G: Generator> Iterator for Samples<G> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.source.tick())
+    }
+}
+
+/// Render the next num_samples of a generator.
+pub fn render<G: Generator + ?Sized>(generator: &mut G, num_samples: usize) -> Vec<f32> {
+    let mut out_vec: Vec<f32> = vec![0.0; num_samples];
+    generator.process(&mut out_vec);
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dsp::biquad::{Biquad, FilterType, BUTTERWORTH_Q};
+    use synth::oscillator::{Tone, Waveform};
+
+    #[test]
+    fn blocks_match_a_whole_render() {
+        let sample_rate = 44100.0;
+        let whole = {
+            let mut filter = Biquad::new(FilterType::LowPass, 800.0, BUTTERWORTH_Q, sample_rate);
+            let mut samples = render(&mut Tone::new(Waveform::Saw, 220.0, sample_rate), 10000);
+            filter.process_buffer(&mut samples);
+            samples
+        };
+
+        let mut filter = Biquad::new(FilterType::LowPass, 800.0, BUTTERWORTH_Q, sample_rate);
+        let mut chained = Tone::new(Waveform::Saw, 220.0, sample_rate)
+                              .chain(move |b: &mut [f32]| filter.process_buffer(b));
+        let mut blocks: Vec<f32> = Vec::new();
+        let mut block = [0.0f32; 300];
+        while blocks.len() < whole.len() {
+            chained.process(&mut block);
+            blocks.extend_from_slice(&block);
+        }
+        assert_eq!(&blocks[..whole.len()], &whole[..]);
+
+        // Sample by sample through the iterator, boxed as a source chosen at run time would be.
+        let boxed: Box<dyn Generator> = Box::new(Tone::new(Waveform::Saw, 220.0, sample_rate));
+        let mut filter = Biquad::new(FilterType::LowPass, 800.0, BUTTERWORTH_Q, sample_rate);
+        let iterated: Vec<f32> = boxed.chain(move |b: &mut [f32]| filter.process_buffer(b))
+                                      .samples().take(whole.len()).collect();
+        assert_eq!(iterated, whole);
+    }
+}
@@ -1,147 +1,655 @@
+use std::f64::consts;
+use std::fs::File;
+
 use rand;
+use rand::Rng;
+use rand::distributions::{IndependentSample, Range};
+use wavfile::read_wav;
+
+use dsp::biquad::{Biquad, FilterType, BUTTERWORTH_Q};
+use synth::generator::{Generator, render};
+use synth::lfo::Lfo;
+use synth::noise::seeded_rng;
+
+// Loss factor applied each trip around the ring.
+const DEFAULT_DAMPING: f32 = 0.9940;
+
+// Number of first order allpasses giving the string its stiffness.
+const DISPERSION_STAGES: usize = 4;
+
+// Cutoff of the filtered noise excitation when none is given.
+const DEFAULT_EXCITATION_CUTOFF: f64 = 2000.0;
+
+/// What the ring is filled with on a pluck.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Excitation {
+    /// White noise, the original Karplus-Strong pluck.
+    Noise,
+    /// White noise through a first order low pass at the given frequency.
+    FilteredNoise(f64),
+    /// A single full scale sample.
+    Impulse,
+    /// Triangle shape of a string pulled aside at the pick position, or the middle if unset.
+    Pick,
+    /// Samples used as they are, cut short or zero padded to the length of the ring.
+    Samples(Vec<f32>),
+}
+
+impl Excitation {
+    /// Parse an excitation as given on the command line: noise, filtered[:HZ], impulse, pick or
+    /// file:PATH for a .wav file.
+    pub fn from_spec(spec: &str) -> Result<Excitation, String> {
+        let (kind, arg) = match spec.find(':') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+        match (kind, arg) {
+            ("noise", None) => Ok(Excitation::Noise),
+            ("filtered", None) => Ok(Excitation::FilteredNoise(DEFAULT_EXCITATION_CUTOFF)),
+            ("filtered", Some(hz)) => {
+                hz.parse().map(Excitation::FilteredNoise).map_err(|_| format!("Bad cutoff '{}'", hz))
+            }
+            ("impulse", None) => Ok(Excitation::Impulse),
+            ("pick", None) => Ok(Excitation::Pick),
+            ("file", Some(path)) => {
+                let wav = File::open(path).and_then(|mut f| read_wav(&mut f))
+                              .map_err(|e| format!("{}: {}", path, e))?;
+                Ok(Excitation::Samples(wav.to_mono()))
+            }
+            _ => Err(format!("Unknown excitation '{}'", spec)),
+        }
+    }
+}
+
+/// Jaffe and Smith's extensions to the string, the defaults give the plain Karplus-Strong sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringParams {
+    /// Seconds for the fundamental to die away by 60dB, None for the fixed loss factor.
+    pub decay_time: Option<f64>,
+    /// Loop lowpass, 0.0 is the two point average and 1.0 no filtering at all.
+    pub brightness: f32,
+    /// Pluck point as a fraction of the string from the bridge, 0.0 for no pick comb.
+    pub pick_position: f32,
+    /// How hard the string is plucked, 0.0 to 1.0. Softer plucks are quieter and darker.
+    pub dynamic_level: f32,
+    /// Dispersion allpass coefficient, 0.0 to 0.9. Stiffer strings have sharper upper partials.
+    pub stiffness: f32,
+    /// Chance of each sample keeping its sign around the loop, 1.0 for a string and 0.5 for the
+    /// drum variant.
+    pub blend: f32,
+    pub excitation: Excitation,
+    /// Seed for the noise excitations, None for different noise on every run.
+    pub seed: Option<u64>,
+}
+
+impl Default for StringParams {
+    fn default() -> StringParams {
+        StringParams {
+            decay_time: None,
+            brightness: 0.0,
+            pick_position: 0.0,
+            dynamic_level: 1.0,
+            stiffness: 0.0,
+            blend: 1.0,
+            excitation: Excitation::Noise,
+            seed: None,
+        }
+    }
+}
 
+/// Plucked string simulated by a Karplus-Strong delay loop.
+///
+/// The loop is a delay line read with third order Lagrange interpolation, the loss filter and
+/// the optional dispersion allpasses, the delay making up the rest of an exact loop period of
+/// sample_rate / frequency (Jaffe and Smith, 1983). Reading between samples lets the delay
+/// slide smoothly when the frequency changes, so glides, bends and vibrato don't click.
 pub struct KarplusStrong {
+    // Circular delay line, write_pos being where the next sample goes.
     ring: Vec<f32>,
-    ring_first: usize,
-    ring_last: usize,
-    ring_size: usize,
+    write_pos: usize,
+    // Delay read from the ring in samples, easing towards the target after a retune.
+    delay: f64,
+    target_delay: f64,
+    glide_coef: f64,
+    params: StringParams,
+    damping: f32,
+    // Weight of the previous sample in the loss filter, 0.5 for the two point average.
+    stretch: f32,
+    // Last output of the ring, the other half of the loss filter.
+    last_out: f32,
+    // Dispersion allpass coefficient and state for each stage.
+    disp_coef: f32,
+    disp_x1: [f32; DISPERSION_STAGES],
+    disp_y1: [f32; DISPERSION_STAGES],
+    rng: rand::XorShiftRng,
     
     sample_rate: f64,
     frequency: f64,
     ticks: u64,
 }
 
-impl KarplusStrong {
-    fn with_ring_size(size: usize) -> KarplusStrong {
-        KarplusStrong {
-            ring: Vec::with_capacity(size),
-            ring_first: 0,
-            ring_last: size,
-            ring_size: size,
-            sample_rate: 44100.0f64,
-            frequency: 0.0f64,
-            ticks: 0,
-        }
-    }
+// Shortest delay the interpolator can read, with a whole sample either side of the point read.
+const MIN_DELAY: f64 = 2.0;
+
+// Time constant of the delay easing to a new frequency, short enough to follow vibrato.
+const GLIDE_SMOOTHING: f64 = 0.002;
 
-    pub fn with_frequency(freq: f64, sample_rate: f64) -> KarplusStrong {
-        let size = (sample_rate / freq).round() as usize;
+/// Phase delay in samples of the loss filter (1 - s) + s z^-1 at w radians per sample.
+fn loss_filter_delay(stretch: f64, w: f64) -> f64 {
+    -(-stretch * w.sin()).atan2(1.0 - stretch + stretch * w.cos()) / w
+}
+
+/// Gain of the loss filter at w radians per sample.
+fn loss_filter_gain(stretch: f64, w: f64) -> f64 {
+    (1.0 - stretch + stretch * w.cos()).hypot(stretch * w.sin())
+}
+
+/// Phase delay in samples of the allpass (a + z^-1) / (1 + a z^-1) at w radians per sample.
+fn allpass_delay(coef: f64, w: f64) -> f64 {
+    1.0 - 2.0 * (coef * w.sin()).atan2(1.0 + coef * w.cos()) / w
+}
+
+impl KarplusStrong {
+    pub fn with_params(freq: f64, params: &StringParams, sample_rate: f64) -> KarplusStrong {
+        let mut params = params.clone();
+        params.brightness = params.brightness.clamp(0.0, 1.0);
+        params.pick_position = params.pick_position.clamp(0.0, 1.0);
+        params.dynamic_level = params.dynamic_level.clamp(0.0, 1.0);
+        params.stiffness = params.stiffness.clamp(0.0, 0.9);
+        params.blend = params.blend.clamp(0.0, 1.0);
         let mut ks = KarplusStrong {
             ring: Vec::new(),
-            ring_first: 0,
-            ring_last: size,
-            ring_size: size,
-            sample_rate: sample_rate,
+            write_pos: 0,
+            delay: 0.0,
+            target_delay: 0.0,
+            glide_coef: 1.0 - (-1.0 / (GLIDE_SMOOTHING * sample_rate)).exp(),
+            damping: DEFAULT_DAMPING,
+            stretch: 0.5 * (1.0 - params.brightness),
+            last_out: 0.0,
+            disp_coef: -params.stiffness,
+            disp_x1: [0.0; DISPERSION_STAGES],
+            disp_y1: [0.0; DISPERSION_STAGES],
+            rng: seeded_rng(params.seed),
+            params,
+            sample_rate,
             frequency: freq,
             ticks: 0,
         };
-
-        let mut i = 0 ;
-        loop {
-            ks.ring.push( 0.0f32 );
-            i = i + 1;
-            if i > size { break; }
-        }
+        ks.set_frequency(freq);
+        ks.delay = ks.target_delay;
         ks
     }
 
+    /// Retune the string, the delay easing over to the new length in a couple of milliseconds.
+    ///
+    /// Call it every sample for glides and vibrato. With a decay time set the loss factor is
+    /// recalculated too, so the T60 holds at any pitch.
     pub fn set_frequency(&mut self, freq: f64) {
         self.frequency = freq;
-        //if freq requires a longer Vec, reallocate
-        self.ring_size = (self.sample_rate / self.frequency).round() as usize;
-        let cap = self.ring.capacity();
-        if cap < self.ring_size {
-            self.ring.reserve((self.ring_size - cap));
+        let w = 2.0 * consts::PI * freq / self.sample_rate;
+        let stretch = self.stretch as f64;
+        let mut filter_delay = loss_filter_delay(stretch, w);
+        if self.params.stiffness > 0.0 {
+            filter_delay += DISPERSION_STAGES as f64 * allpass_delay(self.disp_coef as f64, w);
+        }
+        // Very high or very stiff strings can't be shortened enough and go flat.
+        self.target_delay = (self.sample_rate / freq - filter_delay).max(MIN_DELAY);
+
+        // Room for the interpolator's taps past the delay. Growing adds silence older than
+        // anything in the ring, so what's sounding carries on.
+        let needed = self.target_delay.ceil() as usize + 3;
+        if needed > self.ring.len() {
+            self.ring.rotate_left(self.write_pos);
+            let mut grown = vec![0.0f32; needed - self.ring.len()];
+            grown.append(&mut self.ring);
+            self.ring = grown;
+            self.write_pos = 0;
         }
+
+        if let Some(t60) = self.params.decay_time {
+            // Loss per trip for -60dB after t60 seconds, less what the loss filter already takes.
+            let per_period = 10.0f64.powf(-3.0 / (t60 * freq));
+            self.damping = (per_period / loss_filter_gain(stretch, w)).min(1.0) as f32;
+        }
+    }
+
+    /// Set the loss factor of the loop, 1.0 being lossless.
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping;
     }
 
+    /// Loss factor of the loop.
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Fill the ring with the excitation, in the range -0.5 to +0.5 apart from an impulse or
+    /// samples from a file.
     pub fn pluck(&mut self) {
-        use rand::distributions::{IndependentSample, Range};
-        // Fill self.ring with random noise in range -0.5 to +0.5
-        let mut random: rand::OsRng = rand::OsRng::new().unwrap();
-        //let rng: &rand
+        // A fresh pluck starts at the new pitch rather than gliding there.
+        self.delay = self.target_delay;
+        let mut excitation = vec![0.0f32; self.delay.round() as usize];
         let between = Range::new(-0.50f32, 0.50f32);
-        let mut i: usize = 0;
+        match self.params.excitation {
+            Excitation::Noise => {
+                for samp in excitation.iter_mut() {
+                    *samp = between.ind_sample(&mut self.rng);
+                }
+            }
+            Excitation::FilteredNoise(cutoff) => {
+                for samp in excitation.iter_mut() {
+                    *samp = between.ind_sample(&mut self.rng);
+                }
+                Biquad::new(FilterType::LowPass1, cutoff, BUTTERWORTH_Q, self.sample_rate)
+                    .process_buffer(&mut excitation);
+                normalize(&mut excitation, 0.5);
+            }
+            Excitation::Impulse => {
+                excitation[0] = 1.0;
+            }
+            Excitation::Pick => {
+                let apex = if self.params.pick_position > 0.0 { self.params.pick_position } else { 0.5 };
+                let len = excitation.len() as f32;
+                for (i, samp) in excitation.iter_mut().enumerate() {
+                    let x = i as f32 / len;
+                    *samp = if x < apex { x / apex } else { (1.0 - x) / (1.0 - apex) };
+                }
+                // No DC left to circulate, it would only decay as slowly as the fundamental.
+                let mean = excitation.iter().sum::<f32>() / len;
+                for samp in excitation.iter_mut() {
+                    *samp -= mean;
+                }
+                normalize(&mut excitation, 0.5);
+            }
+            Excitation::Samples(ref samples) => {
+                for (i, samp) in excitation.iter_mut().enumerate() {
+                    *samp = samples.get(i).cloned().unwrap_or(0.0);
+                }
+            }
+        }
+        self.shape_excitation(&mut excitation);
+
+        // Lay the excitation into the ring so it is read out from its start in order.
+        self.ring.fill(0.0);
+        let len = self.ring.len();
+        let start = self.write_pos + len - excitation.len();
+        for (i, samp) in excitation.into_iter().enumerate() {
+            self.ring[(start + i) % len] = samp;
+        }
+    }
+
+    // Dynamic level lowpass and pick position comb over a fresh excitation.
+    fn shape_excitation(&self, excitation: &mut [f32]) {
+        let level = self.params.dynamic_level;
+        if level < 1.0 {
+            // Mix towards a one pole lowpass at the fundamental as the level drops.
+            let pole = (-2.0 * consts::PI * self.frequency / self.sample_rate).exp() as f32;
+            let mut lowpassed = 0.0f32;
+            for samp in excitation.iter_mut() {
+                lowpassed = (1.0 - pole) * *samp + pole * lowpassed;
+                *samp = level * (level * *samp + (1.0 - level) * lowpassed);
+            }
+        }
 
-        loop {
-            let y_val = between.ind_sample(&mut random);
-            let samp = self.ring.get_mut(i).unwrap();
-            *samp = y_val;
-            i = i + 1;
-            if i > self.ring_size { break; }
+        // Plucking at a fraction of the string cancels the harmonics with a node there. The pick
+        // shape already has the pick position in it.
+        if self.params.excitation == Excitation::Pick {
+            return;
+        }
+        let period = self.sample_rate / self.frequency;
+        let lag = (self.params.pick_position as f64 * period).round() as usize;
+        if lag > 0 && lag < excitation.len() {
+            for i in (lag..excitation.len()).rev() {
+                excitation[i] = 0.5 * (excitation[i] - excitation[i - lag]);
+            }
+            for samp in excitation.iter_mut().take(lag) {
+                *samp *= 0.5;
+            }
         }
+    }
 
+    // Read the ring delay samples back from the next write, third order Lagrange interpolating
+    // between samples.
+    fn read(&self, delay: f64) -> f32 {
+        let len = self.ring.len();
+        let first = delay.floor() as usize - 1;
+        // Fractional position from the first of the four taps, between 1 and 2.
+        let d = (delay - first as f64) as f32;
+        let tap = |k: usize| self.ring[(self.write_pos + 2 * len - first - k) % len];
+        let h0 = -(d - 1.0) * (d - 2.0) * (d - 3.0) / 6.0;
+        let h1 = d * (d - 2.0) * (d - 3.0) / 2.0;
+        let h2 = -d * (d - 1.0) * (d - 3.0) / 2.0;
+        let h3 = d * (d - 1.0) * (d - 2.0) / 6.0;
+        h0 * tap(0) + h1 * tap(1) + h2 * tap(2) + h3 * tap(3)
     }
 
     pub fn sample(&mut self) -> f32 {
-        *self.ring.get(self.ring_first).unwrap()
+        self.read(self.delay)
     }
 
     pub fn tick_simulation(&mut self) {
-        let next_index = self.ring_first + 1;
-        let mut second: f32;
-        // Block to allow borrowing ring twice.
-        {
-            let sr: &f32 = self.ring.get(next_index).unwrap();
-            second = *sr;
-        }
-        let first: &mut f32 = self.ring.get_mut(self.ring_first).unwrap();
-        // Karplus-Strong
-        *first = (*first + second) * 0.50f32 * 0.9940f32;
-        self.ring_last = self.ring_first;
-        if next_index >= self.ring_size { self.ring_first = 0; }
-        else { self.ring_first = next_index; }
-        self.ticks = self.ticks + 1;
+        let out = self.read(self.delay);
+        // Karplus-Strong, the loss filter generalising the two point average.
+        let mut filtered = ((1.0 - self.stretch) * out + self.stretch * self.last_out) * self.damping;
+        self.last_out = out;
+        if self.params.blend < 1.0 && self.rng.gen::<f32>() >= self.params.blend {
+            filtered = -filtered;
+        }
+        // Dispersion, high frequencies take less time around the loop.
+        if self.params.stiffness > 0.0 {
+            let a = self.disp_coef;
+            for (x1, y1) in self.disp_x1.iter_mut().zip(self.disp_y1.iter_mut()) {
+                let y = a * filtered + *x1 - a * *y1;
+                *x1 = filtered;
+                *y1 = y;
+                filtered = y;
+            }
+        }
+
+        self.ring[self.write_pos] = filtered;
+        self.write_pos += 1;
+        if self.write_pos >= self.ring.len() { self.write_pos = 0; }
+        self.delay += (self.target_delay - self.delay) * self.glide_coef;
+        self.ticks += 1;
     }
 
 //    pub fn get_ticks(&mut self) -> u64 {
 //        self.ticks
 //    }
-//
-//    pub fn get_ring_size(&self) -> usize {
-//        self.ring_size
-//    }
 }
 
-/// Make a sample based on a single puck on a Karplus-Strong simulated string instrument.
-pub fn generate_one_pluck_sample(run_length: f64, frequency: f64, sample_rate: f64) -> Vec<f32> {
-    let mut ks: KarplusStrong = KarplusStrong::with_frequency(frequency, sample_rate);
+/// Scale samples so the largest magnitude is peak, leaving silence alone.
+fn normalize(samples: &mut [f32], peak: f32) {
+    let max = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if max > 0.0 {
+        for s in samples.iter_mut() {
+            *s *= peak / max;
+        }
+    }
+}
+
+impl Generator for KarplusStrong {
+    fn tick(&mut self) -> f32 {
+        self.tick_simulation();
+        self.sample()
+    }
+}
 
+/// Make a sample based on a single puck on a Karplus-Strong simulated string instrument.
+pub fn generate_one_pluck_sample(run_length: f64, frequency: f64, params: &StringParams, sample_rate: f64) -> Vec<f32> {
+    let mut ks: KarplusStrong = KarplusStrong::with_params(frequency, params, sample_rate);
     ks.pluck();
 
-    let num_samples: u32 = (sample_rate * run_length).round() as u32;
-    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples as usize);
-    let mut i = 0;
-    loop {
-        ks.tick_simulation();
-        if i > num_samples { break; }
-        let samp = ks.sample();
-        out_vec.push( samp );
-        i = i + 1;
+    let num_samples: usize = (sample_rate * run_length).round() as usize;
+    render(&mut ks, num_samples)
+}
+
+/// Level in dB below which the --repeat threshold plucks again, when none is given.
+pub const DEFAULT_RETRIGGER_DB: f64 = -40.0;
+/// Window the envelope follower measures over, in seconds.
+pub const DEFAULT_FOLLOWER_WINDOW: f64 = 0.05;
+
+/// How the envelope follower measures the level of a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Follower {
+    Rms,
+    Peak,
+}
+
+impl Follower {
+    /// Parse a follower name as given on the command line.
+    pub fn from_name(name: &str) -> Option<Follower> {
+        match name {
+            "rms" => Some(Follower::Rms),
+            "peak" => Some(Follower::Peak),
+            _ => None,
+        }
+    }
+}
+
+/// When to pluck the string again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Retrigger {
+    /// Once the level of a window falls below level_db, measured from full scale.
+    Threshold { level_db: f64, window: f64, follower: Follower },
+    /// On every beat at the given tempo.
+    Bpm(f64),
+    /// At each of the given times in seconds, silent until the first.
+    Onsets(Vec<f64>),
+    /// After random intervals between the two times in seconds.
+    Random(f64, f64),
+}
+
+/// Pluck a Karplus-Strong string repeatedly, following the retrigger schedule.
+pub fn generate_ks_retrigger(run_length: f64, frequency: f64, params: &StringParams, retrigger: &Retrigger,
+                             sample_rate: f64) -> Vec<f32> {
+    let mut ks: KarplusStrong = KarplusStrong::with_params(frequency, params, sample_rate);
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let to_samples = |t: f64| (t * sample_rate).round() as usize;
+
+    // Sample positions of the plucks known in advance, the threshold decides as it goes.
+    let schedule: Vec<usize> = match *retrigger {
+        Retrigger::Threshold { .. } => vec![0],
+        Retrigger::Bpm(bpm) => {
+            let beat = 60.0 / bpm;
+            (0..).map(|i| to_samples(i as f64 * beat)).take_while(|n| *n < num_samples).collect()
+        }
+        Retrigger::Onsets(ref times) => {
+            let mut onsets: Vec<usize> = times.iter().map(|t| to_samples(*t)).collect();
+            onsets.sort_unstable();
+            onsets
+        }
+        Retrigger::Random(shortest, longest) => {
+            let mut random = seeded_rng(params.seed.map(|s| s.wrapping_add(1)));
+            let mut onsets: Vec<usize> = Vec::new();
+            let mut t = 0.0;
+            while to_samples(t) < num_samples {
+                onsets.push(to_samples(t));
+                t += if longest > shortest {
+                    Range::new(shortest, longest).ind_sample(&mut random)
+                } else {
+                    shortest
+                };
+            }
+            onsets
+        }
+    };
+
+    let mut next = 0;
+    let (mut sum_squares, mut peak, mut counted) = (0.0f64, 0.0f32, 0usize);
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+    for n in 0..num_samples {
+        if next < schedule.len() && schedule[next] <= n {
+            ks.pluck();
+            while next < schedule.len() && schedule[next] <= n {
+                next += 1;
+            }
+        }
+        let samp = ks.tick();
+        out_vec.push(samp);
+
+        if let Retrigger::Threshold { level_db, window, follower } = *retrigger {
+            sum_squares += (samp as f64).powi(2);
+            peak = peak.max(samp.abs());
+            counted += 1;
+            // Judge each whole window, so a fresh pluck is never measured before it has built up.
+            if counted >= to_samples(window).max(1) {
+                let level = match follower {
+                    Follower::Rms => (sum_squares / counted as f64).sqrt(),
+                    Follower::Peak => peak as f64,
+                };
+                if 20.0 * level.log10() < level_db {
+                    ks.pluck();
+                }
+                sum_squares = 0.0;
+                peak = 0.0;
+                counted = 0;
+            }
+        }
     }
 
     out_vec
 }
 
-/// Create a sample that plucks on Karplus-Strong whenever the sample is below threshold. 
-pub fn generate_ks_threshold(run_length: f64, frequency: f64, sample_rate: f64, thresh: f64) -> Vec<f32> {
-    let mut ks: KarplusStrong = KarplusStrong::with_frequency(frequency, sample_rate);
+/// Single pluck with the loop damping modulated by an LFO.
+///
+/// The LFO output scales the decay rate, a depth of 0.5 swings it between half and one and a half
+/// times the string's own.
+pub fn generate_pluck_lfo_damping(run_length: f64, frequency: f64, params: &StringParams, sample_rate: f64,
+                                  lfo: &mut Lfo) -> Vec<f32> {
+    let mut ks: KarplusStrong = KarplusStrong::with_params(frequency, params, sample_rate);
+    let base_loss = 1.0 - ks.damping();
 
     ks.pluck();
 
     let num_samples: u32 = (sample_rate * run_length).round() as u32;
     let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples as usize);
-    let mut i = 0;
-    loop {
-        ks.tick_simulation();
-        if i > num_samples { break; }
-        let samp = ks.sample();
-        if samp < thresh as f32 {
-            ks.pluck();
+    for _ in 0..num_samples {
+        let loss = base_loss * (1.0 + lfo.tick());
+        ks.set_damping((1.0 - loss).min(1.0));
+        out_vec.push(ks.tick());
+    }
+
+    out_vec
+}
+
+/// Pluck a string once and slide its pitch, evenly in semitones, to end_frequency over glide
+/// seconds, with vibrato from the LFO if given, depth in semitones.
+pub fn generate_pluck_glide(run_length: f64, frequency: f64, end_frequency: f64, glide: f64,
+                            params: &StringParams, sample_rate: f64, mut lfo: Option<&mut Lfo>) -> Vec<f32> {
+    let mut ks: KarplusStrong = KarplusStrong::with_params(frequency, params, sample_rate);
+    ks.pluck();
+
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let glide_samples = (glide * sample_rate).max(1.0);
+    let octaves = (end_frequency / frequency).log2();
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+    for i in 0..num_samples {
+        let mut semitones = 12.0 * octaves * (i as f64 / glide_samples).min(1.0);
+        if let Some(ref mut l) = lfo {
+            semitones += l.tick() as f64;
         }
-        out_vec.push( samp );
-        i = i + 1;
+        ks.set_frequency(frequency * (semitones / 12.0).exp2());
+        out_vec.push(ks.tick());
     }
 
     out_vec
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dsp::biquad::{Biquad, FilterType};
+
+    // Pitch from the average period between upward zero crossings, after band passing around the
+    // fundamental so the crossings are clean.
+    fn measure_pitch(samples: &[f32], nominal: f64, sample_rate: f64) -> f64 {
+        let mut filtered: Vec<f32> = samples.to_vec();
+        for _ in 0..3 {
+            Biquad::new(FilterType::BandPass, nominal, 5.0, sample_rate).process_buffer(&mut filtered);
+        }
+
+        let mut crossings: Vec<f64> = Vec::new();
+        let skip = filtered.len() / 4;
+        for i in skip..filtered.len() - 1 {
+            let (a, b) = (filtered[i] as f64, filtered[i + 1] as f64);
+            if a < 0.0 && b >= 0.0 {
+                crossings.push(i as f64 + a / (a - b));
+            }
+        }
+        let first = crossings[0];
+        let last = crossings[crossings.len() - 1];
+        sample_rate * (crossings.len() - 1) as f64 / (last - first)
+    }
+
+    #[test]
+    fn plucks_are_within_one_cent() {
+        let sample_rate = 44100.0;
+        for freq in [110.0, 440.0, 1000.0, 2000.0, 3520.0, 4186.0].iter() {
+            // A couple of hundred periods, long enough to settle before the high notes decay away.
+            let pluck = generate_one_pluck_sample(200.0 / freq, *freq, &StringParams::default(), sample_rate);
+            let cents = 1200.0 * (measure_pitch(&pluck, *freq, sample_rate) / freq).log2();
+            assert!(cents.abs() < 1.0, "{} Hz is {} cents out", freq, cents);
+        }
+
+        // The loss filter and dispersion delays are tuned out as well.
+        let params = StringParams { brightness: 0.6, stiffness: 0.4, ..StringParams::default() };
+        for freq in [110.0, 440.0, 1000.0].iter() {
+            let pluck = generate_one_pluck_sample(200.0 / freq, *freq, &params, sample_rate);
+            let cents = 1200.0 * (measure_pitch(&pluck, *freq, sample_rate) / freq).log2();
+            assert!(cents.abs() < 1.0, "stiff {} Hz is {} cents out", freq, cents);
+        }
+    }
+
+    #[test]
+    fn seeded_plucks_repeat() {
+        for excitation in [Excitation::Noise, Excitation::FilteredNoise(1000.0)].iter() {
+            let params = StringParams { excitation: excitation.clone(), seed: Some(42), ..StringParams::default() };
+            let pluck = generate_one_pluck_sample(0.1, 440.0, &params, 44100.0);
+            assert_eq!(pluck, generate_one_pluck_sample(0.1, 440.0, &params, 44100.0));
+
+            let reseeded = StringParams { seed: Some(43), ..params };
+            assert!(pluck != generate_one_pluck_sample(0.1, 440.0, &reseeded, 44100.0));
+        }
+        assert_eq!(Excitation::from_spec("filtered:500"), Ok(Excitation::FilteredNoise(500.0)));
+        assert!(Excitation::from_spec("pluck").is_err());
+    }
+
+    #[test]
+    fn retriggers_follow_the_level() {
+        let sample_rate = 44100.0;
+        let params = StringParams { decay_time: Some(0.5), seed: Some(1), ..StringParams::default() };
+        let window = (DEFAULT_FOLLOWER_WINDOW * sample_rate) as usize;
+        let threshold = Retrigger::Threshold { level_db: DEFAULT_RETRIGGER_DB, window: DEFAULT_FOLLOWER_WINDOW,
+                                               follower: Follower::Rms };
+        let repeated = generate_ks_retrigger(1.0, 220.0, &params, &threshold, sample_rate);
+        for block in repeated.chunks(window) {
+            let rms = (block.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / block.len() as f64).sqrt();
+            assert!(20.0 * rms.log10() > DEFAULT_RETRIGGER_DB - 12.0);
+        }
+
+        let onsets = generate_ks_retrigger(0.5, 220.0, &params, &Retrigger::Onsets(vec![0.25]), sample_rate);
+        assert!(onsets[..11025].iter().all(|s| *s == 0.0));
+        assert!(onsets[11025..].iter().any(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn decay_time_is_60db() {
+        let sample_rate = 44100.0;
+        let params = StringParams { decay_time: Some(0.5), ..StringParams::default() };
+        let mut pluck = generate_one_pluck_sample(1.0, 220.0, &params, sample_rate);
+        for _ in 0..3 {
+            Biquad::new(FilterType::BandPass, 220.0, 5.0, sample_rate).process_buffer(&mut pluck);
+        }
+        let rms = |start: usize| {
+            let window = &pluck[start..start + 4410];
+            (window.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / 4410.0).sqrt()
+        };
+        let drop = 20.0 * (rms(22050) / rms(4410)).log10();
+        assert!((drop + 48.0).abs() < 3.0, "dropped {} dB in 0.4 seconds", drop);
+    }
+
+    #[test]
+    fn glides_arrive_without_clicks() {
+        let sample_rate = 44100.0;
+        let params = StringParams { decay_time: Some(4.0), seed: Some(3), ..StringParams::default() };
+        let glide = generate_pluck_glide(1.5, 220.0, 330.0, 0.5, &params, sample_rate, None);
+        let cents = 1200.0 * (measure_pitch(&glide[30000..], 330.0, sample_rate) / 330.0).log2();
+        assert!(cents.abs() < 1.0, "glide ended {} cents out", cents);
+
+        // Clicks would show as high frequencies the string held at one pitch doesn't have.
+        let steady = generate_one_pluck_sample(1.5, 220.0, &params, sample_rate);
+        let highs = |samples: &[f32]| {
+            let mut high = samples[4410..22050].to_vec();
+            for _ in 0..2 {
+                Biquad::new(FilterType::HighPass, 8000.0, BUTTERWORTH_Q, sample_rate).process_buffer(&mut high);
+            }
+            high.iter().map(|s| s * s).sum::<f32>()
+        };
+        assert!(highs(&glide) < 1.1 * highs(&steady));
+    }
+}
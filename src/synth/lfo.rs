@@ -0,0 +1,187 @@
+use std::f64::consts;
+
+use rand;
+use rand::Rng;
+
+use synth::noise::seeded_rng;
+use synth::oscillator::SineOscillator;
+
+/// Waveform of a low frequency oscillator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+    /// New random level held for each cycle.
+    SampleAndHold,
+    /// Random levels each cycle, smoothly interpolated between.
+    Random,
+}
+
+impl LfoShape {
+    /// Parse a shape name as given on the command line.
+    pub fn from_name(name: &str) -> Option<LfoShape> {
+        match name {
+            "sine" => Some(LfoShape::Sine),
+            "triangle" | "tri" => Some(LfoShape::Triangle),
+            "square" => Some(LfoShape::Square),
+            "sh" | "sample-hold" => Some(LfoShape::SampleAndHold),
+            "random" => Some(LfoShape::Random),
+            _ => None,
+        }
+    }
+}
+
+/// What an LFO modulates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoTarget {
+    /// Tremolo, depth is the fraction of gain removed at the bottom of the cycle.
+    Amplitude,
+    /// Vibrato, depth in semitones.
+    Frequency,
+    /// Karplus-Strong loop damping, depth is the fraction the decay rate swings by.
+    Damping,
+    /// Cutoff of the --svf or --ladder filter, depth in octaves.
+    Cutoff,
+    /// Resonance of the --svf or --ladder filter, depth added to and taken from it.
+    Resonance,
+}
+
+impl LfoTarget {
+    /// Parse a target name as given on the command line.
+    pub fn from_name(name: &str) -> Option<LfoTarget> {
+        match name {
+            "amp" | "amplitude" | "tremolo" => Some(LfoTarget::Amplitude),
+            "freq" | "frequency" | "vibrato" => Some(LfoTarget::Frequency),
+            "damping" => Some(LfoTarget::Damping),
+            "cutoff" => Some(LfoTarget::Cutoff),
+            "res" | "resonance" => Some(LfoTarget::Resonance),
+            _ => None,
+        }
+    }
+}
+
+/// Low frequency oscillator, output swings from -depth to +depth.
+#[derive(Debug, Clone)]
+pub struct Lfo {
+    shape: LfoShape,
+    rate: f64,
+    depth: f32,
+    // Phase in cycles, 0.0 to 1.0.
+    phase: f64,
+    sample_rate: f64,
+    rng: rand::XorShiftRng,
+    // Random levels for the current and next cycle.
+    held: f32,
+    next: f32,
+}
+
+impl Lfo {
+    /// LFO whose sample and hold and random levels are repeatable when given a seed.
+    pub fn new(shape: LfoShape, rate: f64, depth: f32, seed: Option<u64>, sample_rate: f64) -> Lfo {
+        let mut rng = seeded_rng(seed);
+        let held = rng.gen_range(-1.0f32, 1.0f32);
+        let next = rng.gen_range(-1.0f32, 1.0f32);
+        Lfo { shape, rate, depth, phase: 0.0, sample_rate, rng, held, next }
+    }
+
+    /// Start the cycle at phase given in degrees.
+    pub fn with_phase(mut self, degrees: f64) -> Lfo {
+        let cycles = degrees / 360.0;
+        self.phase = cycles - cycles.floor();
+        self
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    /// Output the current value then advance one sample.
+    pub fn tick(&mut self) -> f32 {
+        let p = self.phase;
+        let value: f32 = match self.shape {
+            LfoShape::Sine => (p * 2.0 * consts::PI).sin() as f32,
+            LfoShape::Triangle => (if p < 0.5 { 4.0 * p - 1.0 } else { 3.0 - 4.0 * p }) as f32,
+            LfoShape::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::SampleAndHold => self.held,
+            LfoShape::Random => {
+                // Cosine interpolation keeps the slope continuous at cycle boundaries.
+                let x = ((1.0 - (p * consts::PI).cos()) * 0.5) as f32;
+                self.held + (self.next - self.held) * x
+            }
+        };
+
+        self.phase += self.rate / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            self.held = self.next;
+            self.next = self.rng.gen_range(-1.0f32, 1.0f32);
+        }
+
+        value * self.depth
+    }
+}
+
+/// Tremolo or AM, scale samples by a gain swinging between 1.0 - depth and 1.0.
+pub fn amplitude_modulate(samples: &mut [f32], lfo: &mut Lfo) {
+    let floor = 1.0 - 0.5 * lfo.depth();
+    for s in samples.iter_mut() {
+        *s *= floor + 0.5 * lfo.tick();
+    }
+}
+
+/// Ring modulation, multiply samples by the bipolar LFO output.
+pub fn ring_modulate(samples: &mut [f32], lfo: &mut Lfo) {
+    for s in samples.iter_mut() {
+        *s *= lfo.tick();
+    }
+}
+
+/// Sine tone with vibrato, the LFO giving the pitch offset in semitones.
+pub fn generate_vibrato_tone(run_length: f64, frequency: f64, lfo: &mut Lfo, sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut osc = SineOscillator::new(sample_rate);
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+
+    for _ in 0..num_samples {
+        let semitones = lfo.tick() as f64;
+        out_vec.push(osc.tick(frequency * (semitones / 12.0).exp2()));
+    }
+
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_stay_within_depth() {
+        let shapes = [LfoShape::Sine, LfoShape::Triangle, LfoShape::Square,
+                      LfoShape::SampleAndHold, LfoShape::Random];
+        for shape in shapes.iter() {
+            let mut lfo = Lfo::new(*shape, 7.0, 0.5, None, 1000.0);
+            for _ in 0..1000 {
+                assert!(lfo.tick().abs() <= 0.5);
+            }
+        }
+
+        // Seeded random shapes repeat from run to run.
+        let levels = |seed: u64| -> Vec<f32> {
+            let mut lfo = Lfo::new(LfoShape::SampleAndHold, 7.0, 0.5, Some(seed), 1000.0);
+            (0..1000).map(|_| lfo.tick()).collect()
+        };
+        assert_eq!(levels(3), levels(3));
+        assert!(levels(3) != levels(4));
+    }
+
+    #[test]
+    fn tremolo_range() {
+        let mut samples = vec![1.0f32; 1000];
+        let mut lfo = Lfo::new(LfoShape::Triangle, 4.0, 0.5, None, 1000.0);
+        amplitude_modulate(&mut samples, &mut lfo);
+        let min = samples.iter().fold(1.0f32, |m, s| m.min(*s));
+        let max = samples.iter().fold(0.0f32, |m, s| m.max(*s));
+        assert!((min - 0.5).abs() < 1e-3 && (max - 1.0).abs() < 1e-3);
+    }
+}
@@ -1,2 +1,18 @@
+pub mod generator;
 pub mod tone;
 pub mod ksstring;
+pub mod envelope;
+pub mod oscillator;
+pub mod sweep;
+pub mod multitone;
+pub mod telephony;
+pub mod fm;
+pub mod additive;
+pub mod lfo;
+pub mod noise;
+pub mod percussion;
+pub mod voice;
+pub mod pitch;
+pub mod smf;
+pub mod sequence;
+pub mod patch;
@@ -0,0 +1,178 @@
+use std::f64::consts;
+
+use synth::oscillator::SineOscillator;
+use synth::pitch::{Tuning, parse_pitch};
+
+/// A single sine component of a multitone signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Partial {
+    pub frequency: f64,
+    /// Linear amplitude, 1.0 being full scale.
+    pub amplitude: f32,
+    /// Starting phase in radians.
+    pub phase: f64,
+}
+
+impl Partial {
+    pub fn new(frequency: f64) -> Partial {
+        Partial { frequency, amplitude: 1.0, phase: 0.0 }
+    }
+}
+
+/// Starting phases to assign to the partials of a multitone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseMode {
+    /// Keep the phases as given.
+    Given,
+    /// All partials start at phase 0, worst case crest factor.
+    Zero,
+    /// Schroeder phases, weighted by each partial's share of the power.
+    Schroeder,
+    /// Newman phases, pi * (k - 1)^2 / N, for equal amplitude partials.
+    Newman,
+}
+
+impl PhaseMode {
+    /// Parse a phase mode as given on the command line.
+    pub fn from_name(name: &str) -> Option<PhaseMode> {
+        match name {
+            "given" => Some(PhaseMode::Given),
+            "zero" => Some(PhaseMode::Zero),
+            "schroeder" => Some(PhaseMode::Schroeder),
+            "newman" => Some(PhaseMode::Newman),
+            _ => None,
+        }
+    }
+}
+
+/// Convert decibels relative to full scale to a linear amplitude.
+pub fn db_to_amplitude(db: f64) -> f32 {
+    10.0f64.powf(db / 20.0) as f32
+}
+
+/// Parse a list of partials, `440,880@-6dB,1320@-12dB:90`.
+///
+/// Each entry is a frequency, optionally followed by `@` and a level in dB and `:` and a starting
+/// phase in degrees.
+pub fn parse_partials(spec: &str, tuning: &Tuning) -> Result<Vec<Partial>, String> {
+    let mut partials: Vec<Partial> = Vec::new();
+
+    for entry in spec.split(',') {
+        let entry = entry.trim();
+        let (rest, phase) = match entry.find(':') {
+            Some(i) => {
+                let deg: f64 = entry[i + 1..].parse().map_err(|_| format!("Bad phase in '{}'", entry))?;
+                (&entry[..i], deg.to_radians())
+            }
+            None => (entry, 0.0),
+        };
+        let (freq_str, amplitude) = match rest.find('@') {
+            Some(i) => {
+                let level = rest[i + 1..].trim_end_matches("dB").trim_end_matches("db");
+                let db: f64 = level.parse().map_err(|_| format!("Bad level in '{}'", entry))?;
+                (&rest[..i], db_to_amplitude(db))
+            }
+            None => (rest, 1.0),
+        };
+        let frequency = parse_pitch(freq_str, tuning).map_err(|e| format!("Bad frequency in '{}', {}", entry, e))?;
+        partials.push(Partial { frequency, amplitude, phase });
+    }
+
+    Ok(partials)
+}
+
+/// Overwrite the starting phases of partials according to mode.
+pub fn apply_phase_mode(partials: &mut [Partial], mode: PhaseMode) {
+    let n = partials.len() as f64;
+    match mode {
+        PhaseMode::Given => {}
+        PhaseMode::Zero => {
+            for p in partials.iter_mut() {
+                p.phase = 0.0;
+            }
+        }
+        PhaseMode::Newman => {
+            for (k, p) in partials.iter_mut().enumerate() {
+                p.phase = consts::PI * (k as f64) * (k as f64) / n;
+            }
+        }
+        PhaseMode::Schroeder => {
+            // phi_k = phi_1 - 2pi * sum over l < k of (k - l) * p_l, p_l the relative power.
+            let total: f64 = partials.iter().map(|p| (p.amplitude as f64).powi(2)).sum();
+            let power: Vec<f64> = partials.iter().map(|p| (p.amplitude as f64).powi(2) / total).collect();
+            for (k, p) in partials.iter_mut().enumerate() {
+                let acc: f64 = power.iter().take(k).enumerate()
+                                    .map(|(l, pl)| (k - l) as f64 * pl)
+                                    .sum();
+                p.phase = -2.0 * consts::PI * acc;
+            }
+        }
+    }
+}
+
+/// Render the sum of partials for run_length seconds.
+///
+/// Each partial is at exactly its given amplitude for calibrated measurements, so the sum may go
+/// over full scale. That is left to --level or --limit, and reported when the file is written.
+pub fn generate_multitone(run_length: f64, partials: &[Partial], sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut oscs: Vec<SineOscillator> = partials.iter()
+        .map(|p| SineOscillator::with_phase(p.phase, sample_rate))
+        .collect();
+
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+    for _ in 0..num_samples {
+        let mut samp: f32 = 0.0;
+        for (osc, p) in oscs.iter_mut().zip(partials.iter()) {
+            samp += osc.tick(p.frequency) * p.amplitude;
+        }
+        out_vec.push(samp);
+    }
+
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_partial_list() {
+        let partials = parse_partials("440,A5@-6dB,1320@-12dB:90", &Tuning::equal()).unwrap();
+        assert_eq!(partials.len(), 3);
+        assert_eq!(partials[0], Partial::new(440.0));
+        assert!((partials[1].amplitude - 0.501).abs() < 1e-3);
+        assert!((partials[2].phase - consts::FRAC_PI_2).abs() < 1e-9);
+        assert_eq!(partials[1].frequency, 880.0);
+        assert!(parse_partials("440,abc", &Tuning::equal()).is_err());
+    }
+
+    #[test]
+    fn schroeder_lowers_crest_factor() {
+        let mut partials: Vec<Partial> = (1..21).map(|k| Partial::new(100.0 * k as f64)).collect();
+        let zero_peak = generate_multitone(0.01, &partials, 44100.0).iter()
+            .fold(0.0f32, |m, s| m.max(s.abs()));
+        apply_phase_mode(&mut partials, PhaseMode::Schroeder);
+        let schroeder_peak = generate_multitone(0.01, &partials, 44100.0).iter()
+            .fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(schroeder_peak < zero_peak * 0.5);
+    }
+
+    #[test]
+    fn partials_keep_their_levels() {
+        // 100Hz at 0dB and 200Hz at -6dB, a whole number of cycles in 0.1s.
+        let partials = parse_partials("100,200@-6dB", &Tuning::equal()).unwrap();
+        let samples = generate_multitone(0.1, &partials, 8000.0);
+        let level_at = |frequency: f64| -> f64 {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, s) in samples.iter().enumerate() {
+                let w = 2.0 * consts::PI * frequency * i as f64 / 8000.0;
+                re += *s as f64 * w.cos();
+                im += *s as f64 * w.sin();
+            }
+            2.0 * (re * re + im * im).sqrt() / samples.len() as f64
+        };
+        assert!((level_at(100.0) - 1.0).abs() < 1e-3);
+        assert!((level_at(200.0) - 0.501).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,22 @@
+use rand;
+use rand::SeedableRng;
+use rand::distributions::{IndependentSample, Range};
+
+/// Random number generator for noise sources, repeatable when given a seed.
+pub fn seeded_rng(seed: Option<u64>) -> rand::XorShiftRng {
+    match seed {
+        // Xorshift can't be seeded with all zeros, the constants keep the upper words non-zero.
+        Some(s) => rand::XorShiftRng::from_seed([s as u32, (s >> 32) as u32,
+                                                 s as u32 ^ 0x9E37_79B9, (s >> 32) as u32 ^ 0x7F4A_7C15]),
+        None => rand::weak_rng(),
+    }
+}
+
+/// White noise in the range -0.5 to +0.5, the same range as a Karplus-Strong pluck.
+pub fn generate_white_noise(run_length: f64, seed: Option<u64>, sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut random = seeded_rng(seed);
+    let between = Range::new(-0.50f32, 0.50f32);
+
+    (0..num_samples).map(|_| between.ind_sample(&mut random)).collect()
+}
@@ -0,0 +1,464 @@
+use std::fs::File;
+use std::io::Read;
+
+use rand;
+use rand::distributions::{IndependentSample, Range};
+
+use dsp::biquad::{FilterChain, parse_filter_chain};
+use dsp::delay::{Delay, Effect, ModulatedDelay, DEFAULT_CHORUS_DEPTH, DEFAULT_CHORUS_MIX, DEFAULT_DELAY_FEEDBACK,
+                 DEFAULT_DELAY_MIX, DEFAULT_FLANGER_DEPTH, DEFAULT_FLANGER_FEEDBACK, DEFAULT_FLANGER_MIX};
+use dsp::limiter::{Limiter, DEFAULT_LOOKAHEAD, DEFAULT_LIMITER_RELEASE, soft_clip};
+use dsp::reverb::{Reverb, DEFAULT_REVERB_DAMPING, DEFAULT_REVERB_MIX, DEFAULT_REVERB_SIZE};
+use dsp::vcf::{ModulatedFilter, Svf, SvfMode, Ladder};
+use synth::envelope::{Envelope, Curve};
+use synth::generator::Generator;
+use synth::ksstring::{KarplusStrong, StringParams};
+use synth::lfo::{Lfo, LfoShape};
+use synth::multitone::db_to_amplitude;
+use synth::noise::seeded_rng;
+use synth::oscillator::{BlepOscillator, Waveform};
+use synth::pitch::{Tuning, parse_pitch, strip_comment};
+
+// Samples taken through the whole patch at a time.
+const PATCH_BLOCK: usize = 64;
+// Most control ports a node has.
+const MAX_CONTROLS: usize = 2;
+
+/// What a node of a patch does to its inputs.
+enum Unit {
+    Oscillator(BlepOscillator, f64),
+    Pluck(KarplusStrong, f64),
+    Noise(rand::XorShiftRng),
+    Lfo(Lfo),
+    Envelope(Envelope),
+    Ladder(Ladder, f64, f64),
+    Svf(Svf, f64, f64),
+    Filter(FilterChain),
+    Gain(f32),
+    Effect(Box<dyn Effect>),
+    Limiter(Limiter),
+    Clip(f32),
+}
+
+impl Unit {
+    /// Parse a node definition, the kind followed by its settings.
+    fn parse(words: &[&str], tuning: &Tuning, params: &StringParams, sample_rate: f64) -> Result<Unit, String> {
+        let kind = words[0];
+        let num = |i: usize, default: Option<f64>| -> Result<f64, String> {
+            match words.get(i) {
+                Some(w) => w.parse().map_err(|_| format!("bad number '{}' for {}", w, kind)),
+                None => default.ok_or_else(|| format!("too few settings for {}", kind)),
+            }
+        };
+        let word = |i: usize| words.get(i).cloned().ok_or_else(|| format!("too few settings for {}", kind));
+
+        let unit = match kind {
+            "pluck" => {
+                let frequency = parse_pitch(word(1)?, tuning)?;
+                let mut ks = KarplusStrong::with_params(frequency, params, sample_rate);
+                ks.pluck();
+                Unit::Pluck(ks, frequency)
+            }
+            "noise" => Unit::Noise(seeded_rng(params.seed)),
+            "lfo" => {
+                let shape = LfoShape::from_name(word(1)?).ok_or_else(|| format!("unknown lfo shape '{}'", words[1]))?;
+                Unit::Lfo(Lfo::new(shape, num(2, None)?, 1.0, params.seed, sample_rate))
+            }
+            "adsr" => {
+                let mut env = Envelope::ads(num(1, None)?, num(2, None)?, num(3, None)? as f32, Curve::Linear);
+                if words.len() > 5 {
+                    env.release(num(5, None)?, num(4, None)?, Curve::Linear);
+                }
+                Unit::Envelope(env)
+            }
+            "ladder" => Unit::Ladder(Ladder::new(sample_rate), num(1, None)?, num(2, Some(0.0))?),
+            "svf" => {
+                let mode = SvfMode::from_name(word(1)?).ok_or_else(|| format!("unknown svf mode '{}'", words[1]))?;
+                Unit::Svf(Svf::new(mode, sample_rate), num(2, None)?, num(3, Some(0.0))?)
+            }
+            "filter" => Unit::Filter(parse_filter_chain(word(1)?, sample_rate)?),
+            "gain" | "mix" => Unit::Gain(db_to_amplitude(num(1, Some(0.0))?)),
+            "delay" => Unit::Effect(Box::new(Delay::new(num(1, None)?,
+                                                        num(2, Some(DEFAULT_DELAY_FEEDBACK as f64))? as f32,
+                                                        num(3, Some(DEFAULT_DELAY_MIX as f64))? as f32,
+                                                        sample_rate))),
+            "chorus" => {
+                let depth = num(2, Some(DEFAULT_CHORUS_DEPTH))?;
+                let mix = num(3, Some(DEFAULT_CHORUS_MIX as f64))? as f32;
+                Unit::Effect(Box::new(ModulatedDelay::chorus(num(1, None)?, depth, mix, sample_rate)))
+            }
+            "flanger" => {
+                let depth = num(2, Some(DEFAULT_FLANGER_DEPTH))?;
+                let feedback = num(3, Some(DEFAULT_FLANGER_FEEDBACK as f64))? as f32;
+                let mix = num(4, Some(DEFAULT_FLANGER_MIX as f64))? as f32;
+                Unit::Effect(Box::new(ModulatedDelay::flanger(num(1, None)?, depth, feedback, mix, sample_rate)))
+            }
+            "reverb" => {
+                let size = num(1, Some(DEFAULT_REVERB_SIZE as f64))? as f32;
+                let damping = num(2, Some(DEFAULT_REVERB_DAMPING as f64))? as f32;
+                let mix = num(3, Some(DEFAULT_REVERB_MIX as f64))? as f32;
+                Unit::Effect(Box::new(Reverb::new(size, damping, mix, sample_rate)))
+            }
+            "limiter" => {
+                let ceiling = db_to_amplitude(num(1, Some(0.0))?);
+                Unit::Limiter(Limiter::new(ceiling, num(2, Some(DEFAULT_LOOKAHEAD))?, DEFAULT_LIMITER_RELEASE,
+                                           sample_rate))
+            }
+            "clip" => Unit::Clip(db_to_amplitude(num(1, Some(0.0))?)),
+            _ => match Waveform::from_name(kind) {
+                Some(w) => Unit::Oscillator(BlepOscillator::new(w, sample_rate), parse_pitch(word(1)?, tuning)?),
+                None => return Err(format!("unknown node type '{}'", kind)),
+            },
+        };
+        Ok(unit)
+    }
+
+    /// Whether the node processes a signal fed to its in port.
+    fn has_input(&self) -> bool {
+        !matches!(*self, Unit::Oscillator(..) | Unit::Pluck(..) | Unit::Noise(_) | Unit::Lfo(_) |
+                         Unit::Envelope(_))
+    }
+
+    /// Names of the ports modulating the node, frequency and cutoff in octaves, resonance added to
+    /// the node's own or gain as a factor.
+    fn control_ports(&self) -> &'static [&'static str] {
+        match *self {
+            Unit::Oscillator(..) | Unit::Pluck(..) => &["freq"],
+            Unit::Ladder(..) | Unit::Svf(..) => &["cutoff", "res"],
+            Unit::Gain(_) => &["gain"],
+            _ => &[],
+        }
+    }
+
+    /// Fill out from the input and the signals at each control port, the block starting at time
+    /// seconds.
+    fn process(&mut self, input: &[f32], controls: &[Option<&[f32]>; MAX_CONTROLS], time: f64, sample_rate: f64,
+               out: &mut [f32]) {
+        let control = controls[0];
+        // Frequency ratio of the first control signal in octaves, and resonance plus the second.
+        let ratio = |i: usize| control.map_or(1.0, |c| (c[i] as f64).exp2());
+        let res = |resonance: f64, i: usize| controls[1].map_or(resonance, |c| resonance + c[i] as f64);
+        match *self {
+            Unit::Oscillator(ref mut osc, frequency) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = osc.tick(frequency * ratio(i));
+                }
+            }
+            Unit::Pluck(ref mut ks, frequency) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    if control.is_some() {
+                        ks.set_frequency(frequency * ratio(i));
+                    }
+                    *o = ks.tick();
+                }
+            }
+            Unit::Noise(ref mut rng) => {
+                let between = Range::new(-0.50f32, 0.50f32);
+                for o in out.iter_mut() {
+                    *o = between.ind_sample(rng);
+                }
+            }
+            Unit::Lfo(ref mut lfo) => {
+                for o in out.iter_mut() {
+                    *o = lfo.tick();
+                }
+            }
+            Unit::Envelope(ref env) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = env.level_at(time + i as f64 / sample_rate);
+                }
+            }
+            Unit::Ladder(ref mut filter, cutoff, resonance) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = filter.process(input[i], cutoff * ratio(i), res(resonance, i));
+                }
+            }
+            Unit::Svf(ref mut filter, cutoff, resonance) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = filter.process(input[i], cutoff * ratio(i), res(resonance, i));
+                }
+            }
+            Unit::Filter(ref mut chain) => {
+                out.copy_from_slice(input);
+                chain.process_buffer(out);
+            }
+            Unit::Gain(gain) => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = input[i] * gain * control.map_or(1.0, |c| c[i]);
+                }
+            }
+            Unit::Effect(ref mut effect) => {
+                out.copy_from_slice(input);
+                effect.process_buffer(out);
+            }
+            Unit::Limiter(ref mut limiter) => {
+                for (o, x) in out.iter_mut().zip(input.iter()) {
+                    *o = limiter.process(*x);
+                }
+            }
+            Unit::Clip(ceiling) => {
+                out.copy_from_slice(input);
+                soft_clip(out, ceiling);
+            }
+        }
+    }
+}
+
+// A connection into a node, to its in port or to one of its control ports.
+struct Link {
+    from: usize,
+    port: Option<usize>,
+    depth: f32,
+}
+
+struct Node {
+    name: String,
+    unit: Unit,
+    links: Vec<Link>,
+}
+
+/// Graph of sources, modulators and processors, rendered a block at a time from the node
+/// named out.
+pub struct Patch {
+    nodes: Vec<Node>,
+    // Nodes feeding out, each after all of those feeding it.
+    order: Vec<usize>,
+    out: usize,
+    // Last block from each node, and the inputs being gathered for one.
+    outputs: Vec<Vec<f32>>,
+    input: Vec<f32>,
+    controls: Vec<Vec<f32>>,
+    samples_done: u64,
+    sample_rate: f64,
+}
+
+// Node and port named by a connection endpoint, like vcf.cutoff.
+fn endpoint(text: &str) -> (&str, Option<&str>) {
+    match text.find('.') {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    }
+}
+
+/// Parse a patch.
+///
+/// Each line either defines a node as `NAME = TYPE SETTINGS` or connects nodes with arrows,
+/// `a -> b -> c`, a trailing number setting the depth of the connections on the line. A
+/// connection goes to a node's in port unless another port is named, like `env -> vcf.cutoff`.
+/// Signals arriving at the same port are summed, and `#` comments out the rest of a line.
+///
+/// Sources are `sine`, `saw`, `square` or `triangle FREQ`, `pluck FREQ` and `noise`, modulators
+/// `lfo SHAPE RATE` and `adsr A D S R [GATE]`, held at the sustain unless a gate length is
+/// given. Processors are `ladder CUTOFF [RES]`, `svf MODE CUTOFF [RES]`, `filter CHAIN` as
+/// --filter, `gain [DB]` or `mix [DB]`, `delay TIME [FEEDBACK [MIX]]`, `chorus RATE [DEPTH
+/// [MIX]]`, `flanger RATE [DEPTH [FEEDBACK [MIX]]]`, `reverb [SIZE [DAMPING [MIX]]]`, `limiter
+/// [DBFS [LOOKAHEAD]]` and `clip [DBFS]`. Oscillators and plucks have a freq port and the
+/// filters a cutoff port, both in octaves, the filters also a res port added to their
+/// resonance, and gain a gain port multiplying its level.
+///
+/// ```text
+/// osc = saw A2
+/// env = adsr 0.01 0.4 0.2 0.5 1.5
+/// vcf = ladder 300 0.6
+/// echo = delay 0.3 0.4 0.3
+/// out = limiter -1
+///
+/// osc -> vcf -> echo -> out
+/// env -> vcf.cutoff 4
+/// ```
+pub fn parse_patch(text: &str, tuning: &Tuning, params: &StringParams, sample_rate: f64) -> Result<Patch, String> {
+    let mut nodes: Vec<Node> = Vec::new();
+    // Connections as written, resolved once every node is known.
+    let mut wires: Vec<(String, String, Option<String>, f32)> = Vec::new();
+
+    for line in text.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(eq) = line.find('=') {
+            let name = line[..eq].trim();
+            let words: Vec<&str> = line[eq + 1..].split_whitespace().collect();
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '.') || words.is_empty() {
+                return Err(format!("'{}' is not NAME = TYPE SETTINGS", line));
+            }
+            if nodes.iter().any(|n| n.name == name) {
+                return Err(format!("node '{}' defined twice", name));
+            }
+            let unit = Unit::parse(&words, tuning, params, sample_rate).map_err(|e| format!("{}, {}", name, e))?;
+            nodes.push(Node { name: name.to_string(), unit, links: Vec::new() });
+        } else if line.contains("->") {
+            let mut ends: Vec<&str> = line.split("->").map(|e| e.trim()).collect();
+            let last: Vec<&str> = ends[ends.len() - 1].split_whitespace().collect();
+            let depth = match last.len() {
+                1 => 1.0,
+                2 => last[1].parse().map_err(|_| format!("bad depth '{}'", last[1]))?,
+                _ => return Err(format!("'{}' not understood", line)),
+            };
+            let end = ends.len() - 1;
+            ends[end] = last[0];
+            for pair in ends.windows(2) {
+                let (from, from_port) = endpoint(pair[0]);
+                let (to, port) = endpoint(pair[1]);
+                if from_port.is_some() {
+                    return Err(format!("'{}' is an output, it has no ports", pair[0]));
+                }
+                wires.push((from.to_string(), to.to_string(), port.map(|p| p.to_string()), depth));
+            }
+        } else {
+            return Err(format!("'{}' not understood", line));
+        }
+    }
+
+    for (from, to, port, depth) in wires {
+        let (from, to) = (find_node(&nodes, &from)?, find_node(&nodes, &to)?);
+        let unit = &nodes[to].unit;
+        let ports = unit.control_ports();
+        let port = match port.as_deref() {
+            None | Some("in") if unit.has_input() => None,
+            Some(p) if ports.contains(&p) => ports.iter().position(|c| *c == p),
+            _ => return Err(format!("'{}' has no {} port", nodes[to].name, port.as_deref().unwrap_or("in"))),
+        };
+        nodes[to].links.push(Link { from, port, depth });
+    }
+
+    let out = find_node(&nodes, "out")?;
+    let order = evaluation_order(&nodes, out)?;
+    Ok(Patch {
+        outputs: vec![vec![0.0; PATCH_BLOCK]; nodes.len()],
+        nodes,
+        order,
+        out,
+        input: vec![0.0; PATCH_BLOCK],
+        controls: vec![vec![0.0; PATCH_BLOCK]; MAX_CONTROLS],
+        samples_done: 0,
+        sample_rate,
+    })
+}
+
+fn find_node(nodes: &[Node], name: &str) -> Result<usize, String> {
+    nodes.iter().position(|n| n.name == name).ok_or_else(|| format!("no node '{}'", name))
+}
+
+/// Nodes that out depends on, each after those feeding it.
+fn evaluation_order(nodes: &[Node], out: usize) -> Result<Vec<usize>, String> {
+    // 0 unvisited, 1 being visited, 2 placed.
+    fn visit(nodes: &[Node], n: usize, state: &mut [u8], order: &mut Vec<usize>) -> Result<(), String> {
+        match state[n] {
+            2 => return Ok(()),
+            1 => return Err(format!("patch loops back through '{}'", nodes[n].name)),
+            _ => {}
+        }
+        state[n] = 1;
+        for link in nodes[n].links.iter() {
+            visit(nodes, link.from, state, order)?;
+        }
+        state[n] = 2;
+        order.push(n);
+        Ok(())
+    }
+
+    let mut state = vec![0u8; nodes.len()];
+    let mut order: Vec<usize> = Vec::new();
+    visit(nodes, out, &mut state, &mut order)?;
+    Ok(order)
+}
+
+/// Read and parse a patch file.
+pub fn load_patch(path: &str, tuning: &Tuning, params: &StringParams, sample_rate: f64) -> Result<Patch, String> {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+    parse_patch(&text, tuning, params, sample_rate)
+}
+
+impl Patch {
+    // Run every node once, for len samples.
+    fn run_block(&mut self, len: usize) {
+        let time = self.samples_done as f64 / self.sample_rate;
+        let Patch { ref mut nodes, ref order, ref mut outputs, ref mut input, ref mut controls, sample_rate, .. } =
+            *self;
+        for &n in order.iter() {
+            let input = &mut input[..len];
+            for s in input.iter_mut().chain(controls.iter_mut().flat_map(|c| c[..len].iter_mut())) {
+                *s = 0.0;
+            }
+            let mut controlled = [false; MAX_CONTROLS];
+            for link in nodes[n].links.iter() {
+                let into = match link.port {
+                    Some(p) => {
+                        controlled[p] = true;
+                        &mut controls[p][..len]
+                    }
+                    None => &mut *input,
+                };
+                for (s, x) in into.iter_mut().zip(outputs[link.from].iter()) {
+                    *s += x * link.depth;
+                }
+            }
+            let mut active: [Option<&[f32]>; MAX_CONTROLS] = [None; MAX_CONTROLS];
+            for (p, a) in active.iter_mut().enumerate() {
+                if controlled[p] {
+                    *a = Some(&controls[p][..len]);
+                }
+            }
+            nodes[n].unit.process(input, &active, time, sample_rate, &mut outputs[n][..len]);
+        }
+        self.samples_done += len as u64;
+    }
+}
+
+impl Generator for Patch {
+    fn tick(&mut self) -> f32 {
+        let mut one = [0.0f32];
+        self.process(&mut one);
+        one[0]
+    }
+
+    fn process(&mut self, out: &mut [f32]) {
+        for block in out.chunks_mut(PATCH_BLOCK) {
+            self.run_block(block.len());
+            block.copy_from_slice(&self.outputs[self.out][..block.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synth::generator::render;
+    use synth::oscillator::generate_waveform;
+
+    fn parse(text: &str) -> Result<Patch, String> {
+        parse_patch(text, &Tuning::equal(), &StringParams::default(), 44100.0)
+    }
+
+    #[test]
+    fn patches_render_through_their_nodes() {
+        let mut patch = parse("osc = sine C#6\nbeep = sine 1000\nout = mix  # Straight through.\nbeep -> out").unwrap();
+        assert_eq!(render(&mut patch, 1000), generate_waveform(1000.0 / 44100.0, 1000.0, Waveform::Sine, 44100.0));
+
+        let text = "osc = saw A2\nenv = adsr 0.01 0.4 0.2 0.5 1.5\nvcf = ladder 300 0.6\n\
+                    echo = delay 0.3 0.4 0.3\nloud = gain 12\nout = limiter -1\n\
+                    osc -> vcf -> echo -> loud -> out\nenv -> vcf.cutoff 4\nenv -> loud.gain";
+        let samples = render(&mut parse(text).unwrap(), 100000);
+        let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak > 0.8 && peak <= db_to_amplitude(-1.0), "peak {}", peak);
+        // Once the envelope has released it closes the gain after the echoes for good.
+        assert!(samples[90000..].iter().all(|s| *s == 0.0));
+
+        assert!(parse("osc = sine 440").is_err());
+        assert!(parse("osc = sine 440\nout = gain\nosc -> out.freq").is_err());
+        assert!(parse("osc = sine 440\nout = gain\nosc -> lost").is_err());
+        assert!(parse("a = gain\nout = gain\na -> out -> a").is_err());
+        assert!(parse("out = sine 440\nout -> out.freq").is_err());
+
+        // An envelope into the resonance port changes the sound once it rises from zero.
+        let base = "osc = saw 110\nout = svf lp 800\nosc -> out\n";
+        let plain = render(&mut parse(base).unwrap(), 4410);
+        let swept = render(&mut parse(&format!("{}env = adsr 0.05 0 1\nenv -> out.res 0.9", base)).unwrap(), 4410);
+        assert_eq!(swept[0], plain[0]);
+        assert!(swept.iter().zip(plain.iter()).any(|(a, b)| (a - b).abs() > 0.05));
+        assert!(parse(&format!("{}osc -> osc.res", base)).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use rand::distributions::{IndependentSample, Range};
+
+use dsp::biquad::{Biquad, FilterType, BUTTERWORTH_Q};
+use synth::generator::render;
+use synth::ksstring::{KarplusStrong, StringParams};
+use synth::noise::seeded_rng;
+
+// Blend for the drum variant, half the samples flip sign.
+const DRUM_BLEND: f32 = 0.5;
+
+// Frequency ratios of the six square waves in a TR-808 cymbal, inharmonic enough to sound metallic.
+const CYMBAL_RATIOS: [f64; 6] = [1.0, 1.483, 1.800, 2.546, 2.630, 3.897];
+
+// Snare wires are noise high passed above this, by default dying away over the decay time.
+const SNARE_CUTOFF: f64 = 1500.0;
+const SNARE_DECAY: f64 = 0.2;
+
+// Cymbal strings ring for this long by default.
+const CYMBAL_DECAY: f64 = 1.5;
+
+/// Percussion built from Karplus-Strong strings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Percussion {
+    /// The drum variant, loop samples flipping sign at random.
+    Drum,
+    /// Drum body with a burst of high passed noise for the wires.
+    Snare,
+    /// Detuned strings at inharmonic ratios.
+    Cymbal,
+}
+
+impl Percussion {
+    /// Parse a percussion name as given on the command line.
+    pub fn from_name(name: &str) -> Option<Percussion> {
+        match name {
+            "drum" => Some(Percussion::Drum),
+            "snare" => Some(Percussion::Snare),
+            "cymbal" | "metal" => Some(Percussion::Cymbal),
+            _ => None,
+        }
+    }
+
+    /// Blend to use when none is given, the cymbal keeps its strings and rings.
+    pub fn default_blend(&self) -> f32 {
+        match *self {
+            Percussion::Drum | Percussion::Snare => DRUM_BLEND,
+            Percussion::Cymbal => 1.0,
+        }
+    }
+}
+
+// String at frequency, plucked and ready to render.
+fn plucked(frequency: f64, params: &StringParams, sample_rate: f64) -> KarplusStrong {
+    let mut ks = KarplusStrong::with_params(frequency, params, sample_rate);
+    ks.pluck();
+    ks
+}
+
+/// Single hit of percussion tuned to frequency.
+///
+/// The string parameters apply to every string used, with the blend setting how drum like they
+/// are. Flipping signs drains the loop quickly, so near a blend of 0.5 the decay is set mostly by
+/// the period, lower drums ringing longer. Noise is offset from the seed so each string and the
+/// snare wires differ.
+pub fn generate_percussion(run_length: f64, frequency: f64, kind: Percussion, params: &StringParams,
+                           sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut params = params.clone();
+
+    match kind {
+        Percussion::Drum => {
+            render(&mut plucked(frequency, &params, sample_rate), num_samples)
+        }
+        Percussion::Snare => {
+            let mut out_vec = render(&mut plucked(frequency, &params, sample_rate), num_samples);
+            let mut random = seeded_rng(params.seed.map(|s| s.wrapping_add(1)));
+            let between = Range::new(-0.50f32, 0.50f32);
+            let mut wires = Biquad::new(FilterType::HighPass, SNARE_CUTOFF, BUTTERWORTH_Q, sample_rate);
+            // Per sample gain for -60dB over the wires' decay time.
+            let decay = params.decay_time.unwrap_or(SNARE_DECAY);
+            let fall = 10.0f64.powf(-3.0 / (decay * sample_rate)) as f32;
+            let mut level = 1.0f32;
+            for s in out_vec.iter_mut() {
+                *s = 0.5 * *s + 0.5 * level * wires.process(between.ind_sample(&mut random));
+                level *= fall;
+            }
+            out_vec
+        }
+        Percussion::Cymbal => {
+            let mut out_vec = vec![0.0f32; num_samples];
+            let seed = params.seed;
+            params.decay_time.get_or_insert(CYMBAL_DECAY);
+            for (i, ratio) in CYMBAL_RATIOS.iter().enumerate() {
+                params.seed = seed.map(|s| s.wrapping_add(i as u64));
+                let mut ks = plucked(frequency * ratio, &params, sample_rate);
+                for (o, s) in out_vec.iter_mut().zip(render(&mut ks, num_samples)) {
+                    *o += s / CYMBAL_RATIOS.len() as f32;
+                }
+            }
+            // Take out the low end so it rings rather than thuds.
+            Biquad::new(FilterType::HighPass, frequency, BUTTERWORTH_Q, sample_rate).process_buffer(&mut out_vec);
+            out_vec
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn hits_repeat_and_decay() {
+        for kind in [Percussion::Drum, Percussion::Snare, Percussion::Cymbal].iter() {
+            let params = StringParams { blend: kind.default_blend(), seed: Some(5), ..StringParams::default() };
+            let hit = generate_percussion(1.0, 200.0, *kind, &params, 44100.0);
+            assert_eq!(hit.len(), 44100);
+            assert_eq!(hit, generate_percussion(1.0, 200.0, *kind, &params, 44100.0));
+            assert!(rms(&hit[40000..]) < 0.1 * rms(&hit[..4410]), "{:?} doesn't decay", kind);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::fs::File;
+use std::io::Read;
+
+/// Frequency of MIDI note 69, A4.
+pub const A4_FREQUENCY: f64 = 440.0;
+
+// Middle C and A4 as MIDI notes, the scale's tonic and the reference note unless a keyboard
+// mapping says otherwise.
+const MIDDLE_C: i32 = 60;
+const A4_NOTE: i32 = 69;
+
+// Five limit just intonation and Pythagorean scales on C, octave last.
+const JUST_RATIOS: [f64; 12] = [16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 45.0 / 32.0,
+                                3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 9.0 / 5.0, 15.0 / 8.0, 2.0];
+const PYTHAGOREAN_RATIOS: [f64; 12] = [256.0 / 243.0, 9.0 / 8.0, 32.0 / 27.0, 81.0 / 64.0, 4.0 / 3.0,
+                                       729.0 / 512.0, 3.0 / 2.0, 128.0 / 81.0, 27.0 / 16.0, 16.0 / 9.0,
+                                       243.0 / 128.0, 2.0];
+
+fn ratio_to_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+/// Maps MIDI note numbers to frequencies, a scale repeating every period placed on the keys by
+/// a keyboard mapping, in the manner of Scala .scl and .kbm files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    // Cents above the tonic of each degree, from 0.0 up to the period.
+    cents: Vec<f64>,
+    // Scale degree of each key from the middle note on, None for unmapped keys. Empty maps
+    // keys to degrees one for one.
+    map: Vec<Option<i32>>,
+    // Degrees the mapping moves up each time it repeats.
+    map_period: i32,
+    lowest_note: i32,
+    highest_note: i32,
+    middle_note: i32,
+    reference_note: i32,
+    reference_frequency: f64,
+}
+
+impl Tuning {
+    // Scale of cents above the tonic, excluding the tonic and ending with the period, on the
+    // default keyboard.
+    fn from_cents(cents: &[f64]) -> Tuning {
+        let mut all = vec![0.0];
+        all.extend_from_slice(cents);
+        Tuning { cents: all, map: Vec::new(), map_period: cents.len() as i32, lowest_note: 0,
+                 highest_note: 127, middle_note: MIDDLE_C, reference_note: A4_NOTE,
+                 reference_frequency: A4_FREQUENCY }
+    }
+
+    /// Twelve tone equal temperament.
+    pub fn equal() -> Tuning {
+        let cents: Vec<f64> = (1..13).map(|k| k as f64 * 100.0).collect();
+        Tuning::from_cents(&cents)
+    }
+
+    /// Parse a built in tuning name: 12tet, just or pythagorean.
+    pub fn from_name(name: &str) -> Option<Tuning> {
+        let ratios = match name {
+            "12tet" | "equal" | "et" => return Some(Tuning::equal()),
+            "just" => &JUST_RATIOS,
+            "pythagorean" | "pyth" => &PYTHAGOREAN_RATIOS,
+            _ => return None,
+        };
+        let cents: Vec<f64> = ratios.iter().map(|r| ratio_to_cents(*r)).collect();
+        Some(Tuning::from_cents(&cents))
+    }
+
+    /// Parse a Scala .scl scale.
+    ///
+    /// After a description line comes the number of notes, then one pitch per line, in cents
+    /// if it has a decimal point or else as a ratio like 3/2. Lines starting with `!` are
+    /// comments.
+    pub fn from_scala(text: &str) -> Result<Tuning, String> {
+        let mut lines = text.lines().filter(|l| !l.starts_with('!'));
+        lines.next().ok_or("empty scale")?;
+        let count: usize = lines.next().and_then(|l| l.trim().parse().ok())
+                                .ok_or("scale has no note count")?;
+
+        let mut cents: Vec<f64> = Vec::with_capacity(count);
+        for line in lines.map(|l| l.trim()).filter(|l| !l.is_empty()).take(count) {
+            let pitch = line.split_whitespace().next().unwrap_or("");
+            let value = if pitch.contains('.') {
+                pitch.parse().ok()
+            } else {
+                let mut parts = pitch.splitn(2, '/');
+                let num: Option<f64> = parts.next().and_then(|n| n.parse().ok());
+                let den: Option<f64> = parts.next().map_or(Some(1.0), |d| d.parse().ok());
+                match (num, den) {
+                    (Some(n), Some(d)) if n > 0.0 && d > 0.0 => Some(ratio_to_cents(n / d)),
+                    _ => None,
+                }
+            };
+            cents.push(value.ok_or_else(|| format!("bad pitch '{}'", line))?);
+        }
+        if count == 0 || cents.len() != count {
+            return Err(format!("expected {} pitches, found {}", count, cents.len()));
+        }
+        Ok(Tuning::from_cents(&cents))
+    }
+
+    /// Place the scale on the keys with a Scala .kbm keyboard mapping.
+    ///
+    /// The mapping gives its size, the first and last notes to map, the middle note where
+    /// degree 0 sits, the reference note and its frequency, the degree the mapping repeats
+    /// at, then the degree of each key with `x` for keys left silent. A size of 0 maps keys to
+    /// degrees one for one.
+    pub fn with_keymap(mut self, text: &str) -> Result<Tuning, String> {
+        let mut values = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('!'))
+                             .map(|l| l.split_whitespace().next().unwrap_or(""));
+        let mut number = |what: &str| -> Result<f64, String> {
+            values.next().and_then(|v| v.parse().ok()).ok_or_else(|| format!("keymap has no {}", what))
+        };
+        let size = number("map size")? as usize;
+        self.lowest_note = number("first note")? as i32;
+        self.highest_note = number("last note")? as i32;
+        self.middle_note = number("middle note")? as i32;
+        self.reference_note = number("reference note")? as i32;
+        self.reference_frequency = number("reference frequency")?;
+        let period = number("octave degree")? as i32;
+        self.map_period = if period > 0 { period } else { self.cents.len() as i32 - 1 };
+
+        self.map = values.take(size).map(|v| v.parse().ok()).collect();
+        // Keys the mapping runs out before are unmapped.
+        self.map.resize(size, None);
+        if self.degree(self.reference_note).is_none() {
+            return Err("keymap reference note isn't mapped".to_string());
+        }
+        Ok(self)
+    }
+
+    /// Frequency of the reference note, by default A4.
+    pub fn set_reference_frequency(&mut self, frequency: f64) {
+        self.reference_frequency = frequency;
+    }
+
+    // Scale degree of a note counting from the middle note, None if unmapped.
+    fn degree(&self, note: i32) -> Option<i32> {
+        if note < self.lowest_note || note > self.highest_note {
+            return None;
+        }
+        let offset = note - self.middle_note;
+        if self.map.is_empty() {
+            return Some(offset);
+        }
+        let size = self.map.len() as i32;
+        self.map[offset.rem_euclid(size) as usize].map(|d| offset.div_euclid(size) * self.map_period + d)
+    }
+
+    // Cents of a degree above the tonic, degrees past the end of the scale going up by periods.
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let steps = self.cents.len() as i32 - 1;
+        let period = self.cents[steps as usize];
+        degree.div_euclid(steps) as f64 * period + self.cents[degree.rem_euclid(steps) as usize]
+    }
+
+    /// Frequency of a MIDI note number, None for notes the keyboard mapping leaves out.
+    pub fn frequency(&self, note: i32) -> Option<f64> {
+        let reference = self.degree_cents(self.degree(self.reference_note)?);
+        let cents = self.degree_cents(self.degree(note)?);
+        Some(self.reference_frequency * ((cents - reference) / 1200.0).exp2())
+    }
+}
+
+fn read_text(path: &str) -> Result<String, String> {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+    Ok(text)
+}
+
+/// Read a Scala .scl scale file.
+pub fn load_scale(path: &str) -> Result<Tuning, String> {
+    Tuning::from_scala(&read_text(path)?)
+}
+
+/// Read a Scala .kbm keyboard mapping file for the tuning.
+pub fn load_keymap(tuning: Tuning, path: &str) -> Result<Tuning, String> {
+    tuning.with_keymap(&read_text(path)?)
+}
+
+/// MIDI note number of a note name like A4, C#3 or Eb5, C4 being middle C, note 60.
+///
+/// Sharps are `#` and flats `b`, and the octave may be negative down to C-1, note 0.
+pub fn note_number(name: &str) -> Option<i32> {
+    let mut chars = name.chars();
+    let step = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next() {
+        Some('#') => (1, &rest[1..]),
+        Some('b') => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+    Some((octave + 1) * 12 + step + accidental)
+}
+
+/// Frequency of a pitch written in Hz (440 or 440Hz), as a note name (C#3) or as a MIDI note
+/// number (m61), optionally followed by an offset in cents (A4+15c, m60-3.5c).
+///
+/// Note names and numbers go through the tuning, frequencies in Hz don't.
+pub fn parse_pitch(text: &str, tuning: &Tuning) -> Result<f64, String> {
+    let text = text.trim();
+    // The sign of an offset comes after the start, C-1 being a note.
+    let (base, cents) = match text.strip_suffix('c').and_then(|t| t.rfind(['+', '-']).filter(|&i| i > 0)) {
+        Some(i) => {
+            let cents: f64 = text[i..text.len() - 1].parse().map_err(|_| format!("bad cents in '{}'", text))?;
+            (&text[..i], cents)
+        }
+        None => (text, 0.0),
+    };
+
+    let frequency = if let Ok(hz) = base.trim_end_matches("Hz").trim_end_matches("hz").parse::<f64>() {
+        hz
+    } else {
+        let note = match base.strip_prefix('m') {
+            Some(n) => n.parse().ok(),
+            None => note_number(base),
+        };
+        let note = note.ok_or_else(|| format!("'{}' isn't a frequency or note", base))?;
+        tuning.frequency(note).ok_or_else(|| format!("{} isn't mapped in the tuning", base))?
+    };
+
+    Ok(frequency * (cents / 1200.0).exp2())
+}
+
+/// The line before any comment, for files of notes. A '#' starting a word begins a comment,
+/// one inside a note name is a sharp.
+pub fn strip_comment(line: &str) -> &str {
+    let comment = line.char_indices().find(|&(i, c)| {
+        c == '#' && line[..i].chars().last().is_none_or(|p| p.is_whitespace() || p == ',')
+    });
+    &line[..comment.map_or(line.len(), |(i, _)| i)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_names() {
+        assert_eq!(note_number("A4"), Some(69));
+        assert_eq!(note_number("C#3"), Some(49));
+        assert_eq!(note_number("Eb5"), Some(75));
+        assert_eq!(note_number("C-1"), Some(0));
+        assert_eq!(note_number("H2"), None);
+        assert_eq!(note_number("A"), None);
+
+        let equal = Tuning::equal();
+        assert!((parse_pitch("E2", &equal).unwrap() - 82.407).abs() < 0.001);
+        assert!((parse_pitch("m69+1200c", &equal).unwrap() - 880.0).abs() < 1e-9);
+        assert!((parse_pitch("C-1-100c", &equal).unwrap() - 7.717).abs() < 0.001);
+        assert_eq!(parse_pitch("440Hz", &equal), Ok(440.0));
+        assert!(parse_pitch("A4+c", &equal).is_err());
+
+        assert_eq!(strip_comment("pluck C#3 1s # sharp,#comment"), "pluck C#3 1s ");
+        assert_eq!(strip_comment("A4 0.5s,# rest"), "A4 0.5s,");
+    }
+
+    #[test]
+    fn tunings() {
+        let mut just = Tuning::from_name("just").unwrap();
+        just.set_reference_frequency(432.0);
+        // A4 at the reference and E5 a pure fifth above it.
+        assert!((just.frequency(69).unwrap() - 432.0).abs() < 1e-9);
+        assert!((just.frequency(64).unwrap() / just.frequency(60).unwrap() - 1.25).abs() < 1e-9);
+
+        // Five note equal scale on a keymap of three keys, the middle one silent.
+        let scl = "! five.scl\nFive equal\n 5\n!\n240.0\n480.0\n720.0\n960.0\n2/1\n";
+        let kbm = "! 3 keys\n3\n0\n127\n60\n60\n100.0\n2\n0\nx\n1\n";
+        let five = Tuning::from_scala(scl).unwrap().with_keymap(kbm).unwrap();
+        assert_eq!(five.frequency(60), Some(100.0));
+        assert_eq!(five.frequency(61), None);
+        // The mapping repeats every three keys, moving up two degrees of the scale.
+        assert!((five.frequency(62).unwrap() - 100.0 * 0.2f64.exp2()).abs() < 1e-9);
+        assert!((five.frequency(63).unwrap() - 100.0 * 0.4f64.exp2()).abs() < 1e-9);
+        assert!((five.frequency(57).unwrap() - 100.0 * (-0.4f64).exp2()).abs() < 1e-9);
+
+        assert!(Tuning::from_scala("short\n3\n100.0\n").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::Read;
+
+use synth::envelope::{Envelope, Curve};
+use synth::ksstring::{StringParams, generate_one_pluck_sample};
+use synth::multitone::db_to_amplitude;
+use synth::noise::generate_white_noise;
+use synth::oscillator::{Waveform, generate_waveform};
+use synth::percussion::{Percussion, generate_percussion};
+use synth::pitch::{Tuning, parse_pitch, strip_comment};
+
+/// What sounds during a step of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Tone(Waveform),
+    Pluck,
+    Percussion(Percussion),
+    Noise,
+    Silence,
+}
+
+impl Source {
+    /// Parse a generator name as written in a sequence.
+    pub fn from_name(name: &str) -> Option<Source> {
+        match name {
+            "tone" => Some(Source::Tone(Waveform::Sine)),
+            "pluck" => Some(Source::Pluck),
+            "noise" => Some(Source::Noise),
+            "silence" | "rest" => Some(Source::Silence),
+            _ => Waveform::from_name(name).map(Source::Tone)
+                                          .or_else(|| Percussion::from_name(name).map(Source::Percussion)),
+        }
+    }
+}
+
+/// One timed event of a sequence.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub source: Source,
+    pub frequency: f64,
+    /// Length in seconds.
+    pub duration: f64,
+    /// Peak level in dB relative to the generator's own.
+    pub level: f64,
+    pub envelope: Option<Envelope>,
+}
+
+// Number with a unit suffix, like 0.5s or -6dB.
+fn with_unit(token: &str, unit: &str) -> Option<f64> {
+    let split = token.len().checked_sub(unit.len()).filter(|&i| i > 0 && token.is_char_boundary(i))?;
+    if token[split..].eq_ignore_ascii_case(unit) {
+        token[..split].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parse one step, tokens separated by whitespace in any order.
+fn parse_step(text: &str, tuning: &Tuning) -> Result<Step, String> {
+    let mut source: Option<Source> = None;
+    let mut frequency: Option<f64> = None;
+    let mut duration: Option<f64> = None;
+    let mut level: f64 = 0.0;
+    let mut adsr: Option<Vec<f64>> = None;
+
+    for token in text.split_whitespace() {
+        if let Some(s) = Source::from_name(token) {
+            source = Some(s);
+        } else if let Some(f) = with_unit(token, "khz") {
+            frequency = Some(f * 1000.0);
+        } else if let Some(f) = with_unit(token, "hz") {
+            frequency = Some(f);
+        } else if let Some(ms) = with_unit(token, "ms") {
+            duration = Some(ms / 1000.0);
+        } else if let Some(s) = with_unit(token, "s") {
+            duration = Some(s);
+        } else if let Some(db) = with_unit(token, "db") {
+            level = db;
+        } else if let Some(spec) = token.strip_prefix("adsr=") {
+            let times: Vec<f64> = spec.split(':').map(|t| t.parse::<f64>()).collect::<Result<_, _>>()
+                .map_err(|_| format!("'{}' is not attack:decay:sustain:release", token))?;
+            if times.len() != 4 {
+                return Err(format!("'{}' is not attack:decay:sustain:release", token));
+            }
+            adsr = Some(times);
+        } else if let Ok(f) = parse_pitch(token, tuning) {
+            frequency = Some(f);
+        } else {
+            return Err(format!("'{}' not understood", token));
+        }
+    }
+
+    let duration = match duration {
+        Some(d) if d > 0.0 && d.is_finite() => d,
+        _ => return Err(format!("'{}' has no duration", text)),
+    };
+    let source = source.unwrap_or(Source::Tone(Waveform::Sine));
+    let frequency = match (source, frequency) {
+        (_, Some(f)) if f > 0.0 => f,
+        (Source::Noise, _) | (Source::Silence, _) => 0.0,
+        _ => return Err(format!("'{}' has no frequency", text)),
+    };
+    // The release finishes at the end of the step.
+    let envelope = adsr.map(|t| Envelope::adsr(t[0], t[1], t[2] as f32, t[3], (duration - t[3]).max(0.0),
+                                                Curve::Linear));
+
+    Ok(Step { source, frequency, duration, level, envelope })
+}
+
+/// Parse a sequence of steps.
+///
+/// Steps are separated by commas or new lines and play one after another. Each gives an
+/// optional generator (tone, saw, square, triangle, pluck, drum, snare, cymbal, noise or
+/// silence, tone by default), a frequency in Hz or kHz or a pitch in the tuning, a duration in s or
+/// ms, an optional level in dB and an optional `adsr=A:D:S:R` envelope. A word starting with
+/// `#` comments out the rest of the line.
+///
+/// ```text
+/// 440Hz 0.5s, silence 0.2s, pluck E2 2s
+/// saw 1kHz 250ms -12dB adsr=0.01:0.05:0.5:0.1
+/// ```
+pub fn parse_sequence(text: &str, tuning: &Tuning) -> Result<Vec<Step>, String> {
+    let mut steps: Vec<Step> = Vec::new();
+    for line in text.lines() {
+        for step in strip_comment(line).split(',').filter(|s| !s.trim().is_empty()) {
+            steps.push(parse_step(step.trim(), tuning)?);
+        }
+    }
+    if steps.is_empty() {
+        return Err("sequence is empty".to_string());
+    }
+    Ok(steps)
+}
+
+/// Read and parse a sequence file.
+pub fn load_sequence(path: &str, tuning: &Tuning) -> Result<Vec<Step>, String> {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+    parse_sequence(&text, tuning)
+}
+
+/// Render a sequence, plucks using the given string parameters.
+///
+/// Each step starts on the sample nearest its start time, counted from the start of the
+/// sequence, so rounding never builds up over many steps. Percussion uses its own default
+/// blend and each step offsets the noise seed so repeated hits differ.
+pub fn generate_sequence(steps: &[Step], params: &StringParams, sample_rate: f64) -> Vec<f32> {
+    let mut out_vec: Vec<f32> = Vec::new();
+    let mut time: f64 = 0.0;
+
+    for (i, step) in steps.iter().enumerate() {
+        let start = (time * sample_rate).round() as usize;
+        time += step.duration;
+        let len = (time * sample_rate).round() as usize - start;
+        let length = len as f64 / sample_rate;
+
+        let mut params = params.clone();
+        params.seed = params.seed.map(|s| s.wrapping_add(i as u64));
+        let mut samples = match step.source {
+            Source::Tone(w) => generate_waveform(length, step.frequency, w, sample_rate),
+            Source::Pluck => generate_one_pluck_sample(length, step.frequency, &params, sample_rate),
+            Source::Percussion(kind) => {
+                params.blend = kind.default_blend();
+                generate_percussion(length, step.frequency, kind, &params, sample_rate)
+            }
+            Source::Noise => generate_white_noise(length, params.seed, sample_rate),
+            Source::Silence => Vec::new(),
+        };
+        samples.resize(len, 0.0);
+
+        let gain = db_to_amplitude(step.level);
+        for s in samples.iter_mut() {
+            *s *= gain;
+        }
+        if let Some(ref env) = step.envelope {
+            env.apply(&mut samples, sample_rate);
+        }
+        out_vec.extend(samples);
+    }
+
+    out_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_land_on_their_samples() {
+        let text = "440Hz 0.5s, silence 0.2s # gap\npluck E2 2s -6dB, saw C#6 10ms";
+        let steps = parse_sequence(text, &Tuning::equal()).unwrap();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[1].source, Source::Silence);
+        assert_eq!(steps[2].source, Source::Pluck);
+        assert!((steps[2].frequency - 82.407).abs() < 0.001);
+        assert!((steps[3].frequency - 1108.731).abs() < 0.001);
+
+        // A third of a sample's rounding per step must not add up.
+        let steps = parse_sequence(&vec!["100Hz 1ms"; 300].join(","), &Tuning::equal()).unwrap();
+        let out = generate_sequence(&steps, &StringParams::default(), 44100.0);
+        assert_eq!(out.len(), 13230);
+
+        assert!(parse_sequence("440Hz", &Tuning::equal()).is_err());
+        assert!(parse_sequence("pluck 1s", &Tuning::equal()).is_err());
+        assert!(parse_sequence("440Hz 1s loud", &Tuning::equal()).is_err());
+        for text in ["440Hz 1s, 440Hz -0.5s", "440Hz 0s", "440Hz 0ms", "440Hz infs"].iter() {
+            assert!(parse_sequence(text, &Tuning::equal()).is_err(), "{}", text);
+        }
+        // A Kelvin sign lowercases to a shorter k, it must not be sliced through.
+        assert!(parse_sequence("5\u{212A}Hz 1s", &Tuning::equal()).is_err());
+        assert!((parse_sequence("5KHZ 1S", &Tuning::equal()).unwrap()[0].frequency - 5000.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,421 @@
+use std::fs::File;
+use std::io::Read;
+
+use synth::ksstring::StringParams;
+use synth::percussion::Percussion;
+use synth::pitch::Tuning;
+use synth::voice::{PluckVoice, SineVoice, VoiceAllocator};
+
+// Microseconds per quarter note until a tempo event says otherwise, 120 BPM.
+const DEFAULT_TEMPO: u32 = 500_000;
+// Pitch bend range either way, in semitones.
+const BEND_RANGE: f64 = 2.0;
+// Channel 10, General MIDI percussion.
+const DRUM_CHANNEL: u8 = 9;
+// Headroom for each note, full scale once several play at once is scaled down after rendering.
+const NOTE_GAIN: f32 = 0.25;
+
+/// Channel message from a MIDI file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn { key: u8, velocity: u8 },
+    NoteOff { key: u8 },
+    Program(u8),
+    /// Bend from -8192 to 8191, 0 being no bend.
+    PitchBend(i16),
+    Controller { number: u8, value: u8 },
+}
+
+/// Message on a channel at a time in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiEvent {
+    pub time: f64,
+    pub channel: u8,
+    pub message: MidiMessage,
+}
+
+/// Events of all tracks of a MIDI file merged in time order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Song {
+    pub events: Vec<MidiEvent>,
+    /// Time of the last event in seconds.
+    pub length: f64,
+}
+
+// Cursor over the bytes of a track.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = *self.bytes.get(self.pos).ok_or("Track ends early")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> {
+        if self.pos + count > self.bytes.len() {
+            return Err("Track ends early".to_string());
+        }
+        self.pos += count;
+        Ok(())
+    }
+
+    /// Variable length quantity, seven bits a byte with the top bit set on all but the last.
+    fn vlq(&mut self) -> Result<u32, String> {
+        let mut value: u32 = 0;
+        for _ in 0..4 {
+            let b = self.byte()?;
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Variable length number too long".to_string())
+    }
+}
+
+// Event found in a track, before ticks are turned into seconds.
+enum TrackEvent {
+    Tempo(u32),
+    Channel(u8, MidiMessage),
+}
+
+/// Parse the events of one MTrk chunk, with their times in ticks.
+fn parse_track(bytes: &[u8]) -> Result<Vec<(u64, TrackEvent)>, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let mut events: Vec<(u64, TrackEvent)> = Vec::new();
+    let mut tick: u64 = 0;
+    let mut running: Option<u8> = None;
+
+    while reader.pos < bytes.len() {
+        tick += reader.vlq()? as u64;
+        let mut status = reader.byte()?;
+        let first = if status < 0x80 {
+            // Running status, the byte read was the first data byte.
+            let data = status;
+            status = running.ok_or("Data byte without a status")?;
+            data
+        } else if status < 0xF0 {
+            running = Some(status);
+            reader.byte()?
+        } else {
+            // System exclusive and meta events cancel running status.
+            running = None;
+            match status {
+                0xFF => {
+                    let kind = reader.byte()?;
+                    let len = reader.vlq()? as usize;
+                    if kind == 0x51 && len == 3 {
+                        let tempo = (reader.byte()? as u32) << 16 | (reader.byte()? as u32) << 8 | reader.byte()? as u32;
+                        events.push((tick, TrackEvent::Tempo(tempo)));
+                    } else if kind == 0x2F {
+                        break;
+                    } else {
+                        reader.skip(len)?;
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    let len = reader.vlq()? as usize;
+                    reader.skip(len)?;
+                }
+                _ => return Err(format!("Unexpected status byte {:#04x}", status)),
+            }
+            continue;
+        };
+
+        let channel = status & 0x0F;
+        let message = match status & 0xF0 {
+            0x80 => {
+                reader.byte()?;
+                Some(MidiMessage::NoteOff { key: first })
+            }
+            0x90 => {
+                let velocity = reader.byte()?;
+                // Note on with no velocity is the usual way of sending a note off.
+                Some(if velocity == 0 {
+                    MidiMessage::NoteOff { key: first }
+                } else {
+                    MidiMessage::NoteOn { key: first, velocity }
+                })
+            }
+            0xA0 => {
+                reader.byte()?;
+                None
+            }
+            0xB0 => Some(MidiMessage::Controller { number: first, value: reader.byte()? }),
+            0xC0 => Some(MidiMessage::Program(first)),
+            0xD0 => None,
+            _ => {
+                let high = reader.byte()? as i16;
+                Some(MidiMessage::PitchBend(((high << 7) | first as i16) - 8192))
+            }
+        };
+        if let Some(m) = message {
+            events.push((tick, TrackEvent::Channel(channel, m)));
+        }
+    }
+
+    Ok(events)
+}
+
+/// Parse a type 0 or type 1 Standard MIDI File.
+pub fn parse_smf(bytes: &[u8]) -> Result<Song, String> {
+    if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+        return Err("Not a MIDI file".to_string());
+    }
+    let be16 = |pos: usize| (bytes[pos] as u16) << 8 | bytes[pos + 1] as u16;
+    let format = be16(8);
+    if format > 1 {
+        return Err(format!("MIDI file type {} isn't supported", format));
+    }
+    let division = be16(12);
+    if division == 0 {
+        return Err("Bad time division".to_string());
+    }
+
+    // Gather the events of every track, stable sorted so each track keeps its own order.
+    let mut events: Vec<(u64, TrackEvent)> = Vec::new();
+    let mut pos = 8 + ((bytes[4] as usize) << 24 | (bytes[5] as usize) << 16 | (bytes[6] as usize) << 8
+                       | bytes[7] as usize);
+    while pos + 8 <= bytes.len() {
+        let len = (bytes[pos + 4] as usize) << 24 | (bytes[pos + 5] as usize) << 16
+                  | (bytes[pos + 6] as usize) << 8 | bytes[pos + 7] as usize;
+        let end = (pos + 8 + len).min(bytes.len());
+        if &bytes[pos..pos + 4] == b"MTrk" {
+            events.extend(parse_track(&bytes[pos + 8..end])?);
+        }
+        pos = end;
+    }
+    events.sort_by_key(|e| e.0);
+
+    // Ticks to seconds through the tempo map, or fixed with SMPTE frames.
+    let smpte = division & 0x8000 != 0;
+    let smpte_tick = if smpte {
+        let fps = -((division >> 8) as u8 as i8) as f64;
+        1.0 / (fps * (division & 0xFF) as f64)
+    } else {
+        0.0
+    };
+    let mut tempo = DEFAULT_TEMPO;
+    let (mut last_tick, mut last_time) = (0u64, 0.0f64);
+    let mut song = Song { events: Vec::new(), length: 0.0 };
+    for (tick, event) in events {
+        let tick_length = if smpte { smpte_tick } else { tempo as f64 / (division as f64 * 1.0e6) };
+        last_time += (tick - last_tick) as f64 * tick_length;
+        last_tick = tick;
+        match event {
+            TrackEvent::Tempo(t) => tempo = t,
+            TrackEvent::Channel(channel, message) => {
+                song.events.push(MidiEvent { time: last_time, channel, message });
+            }
+        }
+    }
+    song.length = last_time;
+
+    Ok(song)
+}
+
+/// Read and parse a MIDI file.
+pub fn load_smf(path: &str) -> Result<Song, String> {
+    let mut bytes: Vec<u8> = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+    parse_smf(&bytes)
+}
+
+// General MIDI programs that sound plucked: guitars, basses, harp and the plucked ethnic group.
+fn is_plucked(program: u8) -> bool {
+    matches!(program, 24..=39 | 45 | 46 | 104..=107)
+}
+
+// Current state of a MIDI channel.
+#[derive(Clone, Copy)]
+struct Channel {
+    program: u8,
+    bend: f64,
+    volume: f32,
+    pan: f32,
+}
+
+/// Play a song for run_length seconds, returning the left and right channels.
+///
+/// Keys are tuned by the tuning. Plucked General MIDI programs use Karplus-Strong strings with
+/// the given parameters, channel 10 uses drum strings, and everything else sine voices, each
+/// with voices to share between its notes. Pitch bend, volume (CC 7) and pan (CC 10) are
+/// followed, and the mix is scaled down if it would clip.
+pub fn render_song(song: &Song, tuning: &Tuning, params: &StringParams, voices: usize, run_length: f64,
+                   sample_rate: f64) -> (Vec<f32>, Vec<f32>) {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let mut drum_params = params.clone();
+    drum_params.blend = Percussion::Drum.default_blend();
+
+    let mut plucks = VoiceAllocator::new((0..voices).map(|_| PluckVoice::new(params, sample_rate)).collect());
+    let mut drums = VoiceAllocator::new((0..voices).map(|_| PluckVoice::new(&drum_params, sample_rate)).collect());
+    let mut sines = VoiceAllocator::new((0..voices).map(|_| SineVoice::new(sample_rate)).collect());
+    let mut channels = [Channel { program: 0, bend: 0.0, volume: 1.0, pan: 0.0 }; 16];
+    // Notes are numbered by channel and key, so offs and bends find them in any allocator.
+    let note_id = |channel: u8, key: u8| channel as u32 * 128 + key as u32;
+
+    let mut left: Vec<f32> = Vec::with_capacity(num_samples);
+    let mut right: Vec<f32> = Vec::with_capacity(num_samples);
+    let mut next = 0;
+    for i in 0..num_samples {
+        while next < song.events.len() && (song.events[next].time * sample_rate).round() as usize <= i {
+            let event = song.events[next];
+            next += 1;
+            let ch = &mut channels[event.channel as usize];
+            match event.message {
+                MidiMessage::NoteOn { key, velocity } => {
+                    // Keys the tuning leaves unmapped stay silent.
+                    let freq = match tuning.frequency(key as i32) {
+                        Some(f) => f * (ch.bend / 12.0).exp2(),
+                        None => continue,
+                    };
+                    let vel = velocity as f32 / 127.0;
+                    let gain = NOTE_GAIN * ch.volume;
+                    let id = note_id(event.channel, key);
+                    if event.channel == DRUM_CHANNEL {
+                        drums.note_on(id, freq, vel, gain, ch.pan);
+                    } else if is_plucked(ch.program) {
+                        plucks.note_on(id, freq, vel, gain, ch.pan);
+                    } else {
+                        sines.note_on(id, freq, vel, gain, ch.pan);
+                    }
+                }
+                MidiMessage::NoteOff { key } => {
+                    let id = note_id(event.channel, key);
+                    // Drums ring out on their own.
+                    plucks.note_off(id);
+                    sines.note_off(id);
+                }
+                MidiMessage::Program(p) => ch.program = p,
+                MidiMessage::PitchBend(b) => {
+                    ch.bend = b as f64 / 8192.0 * BEND_RANGE;
+                    for key in 0..128u8 {
+                        if let Some(f) = tuning.frequency(key as i32) {
+                            let freq = f * (ch.bend / 12.0).exp2();
+                            plucks.set_frequency(note_id(event.channel, key), freq);
+                            sines.set_frequency(note_id(event.channel, key), freq);
+                        }
+                    }
+                }
+                MidiMessage::Controller { number: 7, value } => ch.volume = value as f32 / 127.0,
+                MidiMessage::Controller { number: 10, value } => {
+                    ch.pan = ((value as f32 - 64.0) / 63.0).clamp(-1.0, 1.0);
+                }
+                MidiMessage::Controller { .. } => {}
+            }
+        }
+
+        let (pl, pr) = plucks.tick();
+        let (dl, dr) = drums.tick();
+        let (sl, sr) = sines.tick();
+        left.push(pl + dl + sl);
+        right.push(pr + dr + sr);
+    }
+
+    let peak = left.iter().chain(right.iter()).fold(0.0f32, |m, s| m.max(s.abs()));
+    if peak > 1.0 {
+        for s in left.iter_mut().chain(right.iter_mut()) {
+            *s /= peak;
+        }
+    }
+
+    (left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        let len = body.len() as u32;
+        c.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        c.extend_from_slice(body);
+        c
+    }
+
+    #[test]
+    fn tempo_map_and_running_status() {
+        // Type 1, 96 ticks a quarter note.
+        let mut file = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+        // Conductor track, tempo halves to 60 BPM after one beat.
+        file.extend(chunk(b"MTrk", &[0x60, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40, 0, 0xFF, 0x2F, 0]));
+        // Note on at the start, off after two beats by running status with velocity 0, then a
+        // bend up.
+        file.extend(chunk(b"MTrk", &[0, 0x91, 60, 100, 0x81, 0x40, 60, 0, 0, 0xE1, 0, 0x60,
+                                     0, 0xFF, 0x2F, 0]));
+
+        let song = parse_smf(&file).unwrap();
+        assert_eq!(song.events.len(), 3);
+        assert_eq!(song.events[0], MidiEvent { time: 0.0, channel: 1,
+                                               message: MidiMessage::NoteOn { key: 60, velocity: 100 } });
+        // Half a second at 120 BPM, then a second at 60.
+        assert!((song.events[1].time - 1.5).abs() < 1e-9);
+        assert_eq!(song.events[1].message, MidiMessage::NoteOff { key: 60 });
+        assert_eq!(song.events[2].message, MidiMessage::PitchBend(4096));
+
+        assert!(parse_smf(b"RIFF....").is_err());
+
+        // No running status carries across a meta event.
+        let mut file = chunk(b"MThd", &[0, 0, 0, 1, 0, 96]);
+        file.extend(chunk(b"MTrk", &[0, 0x90, 60, 100, 0, 0xFF, 0x01, 1, b'x', 0, 60, 0, 0, 0xFF, 0x2F, 0]));
+        assert!(parse_smf(&file).is_err());
+    }
+
+    #[test]
+    fn renders_programs_bends_and_controllers() {
+        let sample_rate = 44100.0;
+        let params = StringParams { seed: Some(1), ..StringParams::default() };
+        let ev = |time: f64, channel: u8, message: MidiMessage| MidiEvent { time, channel, message };
+        let on = |key: u8| MidiMessage::NoteOn { key, velocity: 127 };
+        let render = |events: Vec<MidiEvent>, length: f64| {
+            render_song(&Song { events, length }, &Tuning::equal(), &params, 8, length, sample_rate)
+        };
+        let secs = |from: f64, to: f64| (from * sample_rate) as usize..(to * sample_rate) as usize;
+        let rms = |s: &[f32]| (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt();
+        // Frequency from the rising zero crossings.
+        let frequency = |s: &[f32]| {
+            s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count() as f64 * sample_rate / s.len() as f64
+        };
+
+        // A sine panned hard right and bent up two semitones halfway, beside a note on a channel
+        // turned all the way down.
+        let (left, right) = render(vec![ev(0.0, 0, MidiMessage::Controller { number: 10, value: 127 }),
+                                        ev(0.0, 1, MidiMessage::Controller { number: 7, value: 0 }),
+                                        ev(0.0, 0, on(69)), ev(0.0, 1, on(60)),
+                                        ev(0.5, 0, MidiMessage::PitchBend(8191)),
+                                        ev(1.0, 0, MidiMessage::NoteOff { key: 69 })], 1.2);
+        assert!(left.iter().all(|s| s.abs() < 1.0e-6));
+        assert!((right.iter().fold(0.0f32, |m, s| m.max(s.abs())) - NOTE_GAIN).abs() < 1.0e-3);
+        assert!((frequency(&right[secs(0.1, 0.5)]) - 440.0).abs() < 5.0);
+        assert!((frequency(&right[secs(0.6, 1.0)]) - 493.9).abs() < 5.0);
+        assert!(right[secs(1.1, 1.2)].iter().all(|s| *s == 0.0));
+
+        // A guitar program plucks a decaying string, where a sine holds its level.
+        let held = |program: u8| {
+            let (left, _) = render(vec![ev(0.0, 0, MidiMessage::Program(program)), ev(0.0, 0, on(45))], 1.0);
+            rms(&left[secs(0.8, 1.0)]) / rms(&left[secs(0.0, 0.2)])
+        };
+        assert!((held(0) - 1.0).abs() < 0.05);
+        assert!(held(25) < 0.5);
+
+        // Strings are damped once let go, drums ring on.
+        let released = |channel: u8| {
+            let (left, _) = render(vec![ev(0.0, channel, MidiMessage::Program(25)), ev(0.0, channel, on(45)),
+                                        ev(0.1, channel, MidiMessage::NoteOff { key: 45 })], 0.5);
+            rms(&left[secs(0.4, 0.5)])
+        };
+        assert!(released(DRUM_CHANNEL) > released(0) * 10.0);
+
+        // Enough notes at once to clip are scaled back to full scale.
+        let chord: Vec<MidiEvent> = [48, 52, 55, 60, 64, 67, 72, 76].iter().map(|k| ev(0.0, 0, on(*k))).collect();
+        let (left, right) = render(chord, 0.5);
+        let peak = left.iter().chain(right.iter()).fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!((peak - 1.0).abs() < 1.0e-6);
+    }
+}
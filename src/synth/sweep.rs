@@ -0,0 +1,109 @@
+use std::f64::consts;
+
+use synth::oscillator::SineOscillator;
+
+/// How the frequency of a sweep moves from start to end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SweepKind {
+    /// Constant Hz per second.
+    Linear,
+    /// Constant octaves per second, the Farina exponential sine sweep. Also known as log sweep.
+    Exponential,
+}
+
+impl SweepKind {
+    /// Parse a sweep type as given on the command line.
+    pub fn from_name(name: &str) -> Option<SweepKind> {
+        match name {
+            "lin" | "linear" => Some(SweepKind::Linear),
+            "log" | "exp" | "logarithmic" | "exponential" => Some(SweepKind::Exponential),
+            _ => None,
+        }
+    }
+
+    /// Instantaneous frequency at position x, 0.0 to 1.0, through the sweep.
+    fn frequency_at(&self, start: f64, end: f64, x: f64) -> f64 {
+        match *self {
+            SweepKind::Linear => start + (end - start) * x,
+            SweepKind::Exponential => start * (end / start).powf(x),
+        }
+    }
+}
+
+/// Render a sine sweep from start to end frequency over run_length seconds, both above 0Hz.
+///
+/// The first fade_in and last fade_out seconds are shaped by half Hann windows so the sweep
+/// starts and stops without the clicks that smear a deconvolved response.
+pub fn generate_sweep(run_length: f64, start: f64, end: f64, kind: SweepKind, fade_in: f64, fade_out: f64,
+                      sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let fade_in = ((fade_in * sample_rate).round() as usize).min(num_samples);
+    let fade_out = ((fade_out * sample_rate).round() as usize).min(num_samples);
+    // Rises from 0.0 at the edge of the file to 1.0 len samples in.
+    let window = |from_edge: usize, len: usize| -> f64 {
+        if from_edge < len { 0.5 - 0.5 * (consts::PI * from_edge as f64 / len as f64).cos() } else { 1.0 }
+    };
+    let mut osc = SineOscillator::new(sample_rate);
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let x = i as f64 / num_samples as f64;
+        let gain = window(i, fade_in) * window(num_samples - 1 - i, fade_out);
+        out_vec.push((osc.tick(kind.frequency_at(start, end, x)) as f64 * gain) as f32);
+    }
+
+    out_vec
+}
+
+/// Create the inverse filter for a rendered sweep, for deconvolving a recorded response.
+///
+/// The filter is the time reversed sweep. For exponential sweeps it is also weighted by the
+/// instantaneous frequency, -6dB per octave going backwards, to flatten the pink spectrum of the
+/// sweep. It is scaled so the sweep convolved with its inverse peaks at 1.0.
+pub fn inverse_filter(sweep: &[f32], start: f64, end: f64, kind: SweepKind) -> Vec<f32> {
+    let len = sweep.len();
+    let weight = |i: usize| -> f64 {
+        match kind {
+            SweepKind::Linear => 1.0,
+            SweepKind::Exponential => kind.frequency_at(start, end, i as f64 / len as f64) / end.max(start),
+        }
+    };
+
+    // Convolution at the peak lag is the weighted energy of the sweep.
+    let mut peak: f64 = 0.0;
+    for (i, s) in sweep.iter().enumerate() {
+        peak += (*s as f64) * (*s as f64) * weight(i);
+    }
+    let scale = if peak > 0.0 { 1.0 / peak } else { 0.0 };
+
+    sweep.iter().enumerate().rev()
+         .map(|(i, s)| (*s as f64 * weight(i) * scale) as f32)
+         .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_filter_peak_is_unity() {
+        let sweep = generate_sweep(0.1, 100.0, 10000.0, SweepKind::Exponential, 0.0, 0.0, 44100.0);
+        let inverse = inverse_filter(&sweep, 100.0, 10000.0, SweepKind::Exponential);
+        let len = sweep.len();
+        let mut peak: f64 = 0.0;
+        for i in 0..len {
+            peak += sweep[i] as f64 * inverse[len - 1 - i] as f64;
+        }
+        assert!((peak - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fades_start_and_end_at_zero() {
+        let plain = generate_sweep(0.1, 100.0, 1000.0, SweepKind::Linear, 0.0, 0.0, 1000.0);
+        let faded = generate_sweep(0.1, 100.0, 1000.0, SweepKind::Linear, 0.01, 0.02, 1000.0);
+        assert_eq!((faded[0], faded[99]), (0.0, 0.0));
+        // Half way through the fade in the window is at half gain.
+        assert!((faded[5] - 0.5 * plain[5]).abs() < 1e-6);
+        assert_eq!(&faded[10..80], &plain[10..80]);
+    }
+}
@@ -0,0 +1,180 @@
+use synth::multitone::{Partial, db_to_amplitude, generate_multitone};
+
+// Level of each tone of a dual tone pair, leaves headroom for twist before the pair clips.
+const TONE_LEVEL_DB: f64 = -7.0;
+
+/// DTMF low (row) and high (column) group frequencies for a digit.
+pub fn dtmf_frequencies(digit: char) -> Option<(f64, f64)> {
+    let (row, col) = match digit.to_ascii_uppercase() {
+        '1' => (0, 0), '2' => (0, 1), '3' => (0, 2), 'A' => (0, 3),
+        '4' => (1, 0), '5' => (1, 1), '6' => (1, 2), 'B' => (1, 3),
+        '7' => (2, 0), '8' => (2, 1), '9' => (2, 2), 'C' => (2, 3),
+        '*' => (3, 0), '0' => (3, 1), '#' => (3, 2), 'D' => (3, 3),
+        _ => return None,
+    };
+    let low = [697.0, 770.0, 852.0, 941.0];
+    let high = [1209.0, 1336.0, 1477.0, 1633.0];
+    Some((low[row], high[col]))
+}
+
+/// Append seconds of silence to a signal.
+pub fn append_silence(out: &mut Vec<f32>, seconds: f64, sample_rate: f64) {
+    let num_samples: usize = (seconds * sample_rate).round() as usize;
+    let len = out.len();
+    out.resize(len + num_samples, 0.0f32);
+}
+
+/// Render one dual tone, twist being the level of the high tone relative to the low in dB.
+fn dual_tone(length: f64, low: f64, high: f64, twist: f64, sample_rate: f64) -> Vec<f32> {
+    let pair = [
+        Partial { frequency: low, amplitude: db_to_amplitude(TONE_LEVEL_DB), phase: 0.0 },
+        Partial { frequency: high, amplitude: db_to_amplitude(TONE_LEVEL_DB + twist), phase: 0.0 },
+    ];
+    generate_multitone(length, &pair, sample_rate)
+}
+
+/// Render a string of DTMF digits, each tone followed by gap seconds of silence.
+///
+/// Dashes and spaces are skipped so numbers can be written the way they're printed.
+pub fn generate_dtmf(digits: &str, tone_length: f64, gap: f64, twist: f64, sample_rate: f64) -> Result<Vec<f32>, String> {
+    let mut out_vec: Vec<f32> = Vec::new();
+
+    for d in digits.chars() {
+        if d == '-' || d == ' ' {
+            continue;
+        }
+        let (low, high) = match dtmf_frequencies(d) {
+            Some(f) => f,
+            None => return Err(format!("'{}' is not a DTMF digit", d)),
+        };
+        out_vec.extend(dual_tone(tone_length, low, high, twist, sample_rate));
+        append_silence(&mut out_vec, gap, sample_rate);
+    }
+
+    Ok(out_vec)
+}
+
+/// Call progress tones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallProgress {
+    Dial,
+    Busy,
+    Ringback,
+    /// Special information tone, three rising tones before an intercept message.
+    Sit,
+}
+
+impl CallProgress {
+    /// Parse a call progress tone name as given on the command line.
+    pub fn from_name(name: &str) -> Option<CallProgress> {
+        match name {
+            "dial" => Some(CallProgress::Dial),
+            "busy" => Some(CallProgress::Busy),
+            "ringback" | "ring" => Some(CallProgress::Ringback),
+            "sit" => Some(CallProgress::Sit),
+            _ => None,
+        }
+    }
+}
+
+/// Regional tone plans for call progress tones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    /// North American precise tone plan.
+    NorthAmerica,
+    UnitedKingdom,
+    /// CEPT recommended tones used across most of Europe.
+    Europe,
+}
+
+impl Region {
+    /// Parse a region name as given on the command line.
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name {
+            "na" | "us" => Some(Region::NorthAmerica),
+            "uk" | "gb" => Some(Region::UnitedKingdom),
+            "eu" => Some(Region::Europe),
+            _ => None,
+        }
+    }
+}
+
+/// One step of a cadence, frequencies to sum for seconds. No frequencies is silence.
+type CadenceStep = (&'static [f64], f64);
+
+// ITU-T E.180 special information tone, also used in the UK.
+const ITU_SIT: &[CadenceStep] = &[
+    (&[950.0], 0.330), (&[1400.0], 0.330), (&[1800.0], 0.330), (&[], 1.0),
+];
+
+/// Repeating cadence for a tone in a region. A single step is a continuous tone.
+fn cadence(tone: CallProgress, region: Region) -> &'static [CadenceStep] {
+    match (region, tone) {
+        (Region::NorthAmerica, CallProgress::Dial) => &[(&[350.0, 440.0], 1.0)],
+        (Region::NorthAmerica, CallProgress::Busy) => &[(&[480.0, 620.0], 0.5), (&[], 0.5)],
+        (Region::NorthAmerica, CallProgress::Ringback) => &[(&[440.0, 480.0], 2.0), (&[], 4.0)],
+        (Region::NorthAmerica, CallProgress::Sit) => &[
+            (&[913.8], 0.274), (&[1370.6], 0.274), (&[1776.7], 0.380), (&[], 1.0),
+        ],
+        (Region::UnitedKingdom, CallProgress::Dial) => &[(&[350.0, 450.0], 1.0)],
+        (Region::UnitedKingdom, CallProgress::Busy) => &[(&[400.0], 0.375), (&[], 0.375)],
+        (Region::UnitedKingdom, CallProgress::Ringback) => &[
+            (&[400.0, 450.0], 0.4), (&[], 0.2), (&[400.0, 450.0], 0.4), (&[], 2.0),
+        ],
+        (Region::Europe, CallProgress::Dial) => &[(&[425.0], 1.0)],
+        (Region::Europe, CallProgress::Busy) => &[(&[425.0], 0.5), (&[], 0.5)],
+        (Region::Europe, CallProgress::Ringback) => &[(&[425.0], 1.0), (&[], 4.0)],
+        (_, CallProgress::Sit) => ITU_SIT,
+    }
+}
+
+/// Render a call progress tone for run_length seconds, repeating its cadence.
+pub fn generate_call_progress(run_length: f64, tone: CallProgress, region: Region, sample_rate: f64) -> Vec<f32> {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let steps = cadence(tone, region);
+
+    // Continuous tones are rendered in one go to avoid phase jumps between steps.
+    if steps.len() == 1 {
+        return render_step(steps[0].0, run_length, sample_rate);
+    }
+
+    let mut out_vec: Vec<f32> = Vec::with_capacity(num_samples);
+    'cadence: loop {
+        for step in steps {
+            if out_vec.len() >= num_samples {
+                break 'cadence;
+            }
+            out_vec.extend(render_step(step.0, step.1, sample_rate));
+        }
+    }
+    out_vec.truncate(num_samples);
+
+    out_vec
+}
+
+fn render_step(freqs: &[f64], length: f64, sample_rate: f64) -> Vec<f32> {
+    let partials: Vec<Partial> = freqs.iter().map(|f| {
+        Partial { frequency: *f, amplitude: db_to_amplitude(TONE_LEVEL_DB), phase: 0.0 }
+    }).collect();
+    generate_multitone(length, &partials, sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dtmf_length_and_digits() {
+        let out = generate_dtmf("555-1234#", 0.1, 0.05, 0.0, 8000.0).unwrap();
+        assert_eq!(out.len(), 8 * (800 + 400));
+        assert!(generate_dtmf("12x", 0.1, 0.05, 0.0, 8000.0).is_err());
+    }
+
+    #[test]
+    fn busy_cadence_is_silent_in_gaps() {
+        let out = generate_call_progress(2.0, CallProgress::Busy, Region::Europe, 8000.0);
+        assert_eq!(out.len(), 16000);
+        assert!(out[4000..8000].iter().all(|s| *s == 0.0));
+        assert!(out[8000..12000].iter().any(|s| *s != 0.0));
+    }
+}
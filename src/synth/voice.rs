@@ -0,0 +1,344 @@
+use dsp::stereo::pan_gains;
+use synth::ksstring::{KarplusStrong, StringParams};
+use synth::oscillator::SineOscillator;
+
+// Attack and release ramps of a sine voice, and how fast a released string is damped.
+const SINE_ATTACK: f64 = 0.005;
+const SINE_RELEASE: f64 = 0.05;
+const PLUCK_RELEASE: f64 = 0.1;
+
+// A pluck voice is free again once its level has fallen this low.
+const SILENT_LEVEL: f32 = 1.0e-4;
+
+/// One voice of a polyphonic instrument.
+pub trait Voice {
+    /// Start a note at velocity, 0.0 to 1.0.
+    fn note_on(&mut self, frequency: f64, velocity: f32);
+    /// Let the note die away.
+    fn note_off(&mut self);
+    /// Change the pitch of the sounding note, for pitch bends.
+    fn set_frequency(&mut self, frequency: f64);
+    /// Output the next sample.
+    fn tick(&mut self) -> f32;
+    /// Whether the voice is still making sound.
+    fn is_active(&self) -> bool;
+}
+
+/// Sine voice with short linear attack and release ramps, so notes start and stop without clicks.
+pub struct SineVoice {
+    osc: SineOscillator,
+    frequency: f64,
+    level: f32,
+    target: f32,
+    step: f32,
+    sample_rate: f64,
+}
+
+impl SineVoice {
+    pub fn new(sample_rate: f64) -> SineVoice {
+        SineVoice { osc: SineOscillator::new(sample_rate), frequency: 0.0, level: 0.0, target: 0.0,
+                    step: 0.0, sample_rate }
+    }
+
+    fn ramp_to(&mut self, target: f32, time: f64) {
+        self.target = target;
+        self.step = ((target - self.level).abs() as f64 / (time * self.sample_rate)).max(1.0e-9) as f32;
+    }
+}
+
+impl Voice for SineVoice {
+    fn note_on(&mut self, frequency: f64, velocity: f32) {
+        self.frequency = frequency;
+        self.ramp_to(velocity, SINE_ATTACK);
+    }
+
+    fn note_off(&mut self) {
+        self.ramp_to(0.0, SINE_RELEASE);
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    fn tick(&mut self) -> f32 {
+        if self.level < self.target {
+            self.level = (self.level + self.step).min(self.target);
+        } else if self.level > self.target {
+            self.level = (self.level - self.step).max(self.target);
+        }
+        self.osc.tick(self.frequency) * self.level
+    }
+
+    fn is_active(&self) -> bool {
+        self.level > 0.0 || self.target > 0.0
+    }
+}
+
+/// Karplus-Strong string voice, each note plucking a fresh string.
+pub struct PluckVoice {
+    string: Option<KarplusStrong>,
+    params: StringParams,
+    velocity: f32,
+    // Decaying peak of the output, to tell when the string has gone quiet.
+    level: f32,
+    released: bool,
+    notes: u64,
+    sample_rate: f64,
+}
+
+impl PluckVoice {
+    pub fn new(params: &StringParams, sample_rate: f64) -> PluckVoice {
+        PluckVoice { string: None, params: params.clone(), velocity: 0.0, level: 0.0, released: false,
+                     notes: 0, sample_rate }
+    }
+}
+
+// Damp a released string like a hand laid on it.
+fn damp(ks: &mut KarplusStrong) {
+    let per_period = 10.0f64.powf(-3.0 / (PLUCK_RELEASE * ks.frequency()));
+    ks.set_damping(per_period as f32);
+}
+
+impl Voice for PluckVoice {
+    fn note_on(&mut self, frequency: f64, velocity: f32) {
+        let mut params = self.params.clone();
+        // A different seed for every note so repeated notes don't sound identical.
+        params.seed = params.seed.map(|s| s.wrapping_add(self.notes));
+        self.notes += 1;
+
+        let mut ks = KarplusStrong::with_params(frequency, &params, self.sample_rate);
+        ks.pluck();
+        self.string = Some(ks);
+        self.velocity = velocity;
+        self.level = 1.0;
+        self.released = false;
+    }
+
+    fn note_off(&mut self) {
+        self.released = true;
+        if let Some(ref mut ks) = self.string {
+            damp(ks);
+        }
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        if let Some(ref mut ks) = self.string {
+            // Retuning sets the string's own loss again.
+            ks.set_frequency(frequency);
+            if self.released {
+                damp(ks);
+            }
+        }
+    }
+
+    fn tick(&mut self) -> f32 {
+        match self.string {
+            Some(ref mut ks) => {
+                ks.tick_simulation();
+                let out = ks.sample() * self.velocity;
+                self.level = out.abs().max(self.level * 0.9995);
+                out
+            }
+            None => 0.0,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.string.is_some() && self.level > SILENT_LEVEL
+    }
+}
+
+// A voice and the note it is playing.
+struct Slot<V: Voice> {
+    voice: V,
+    note: Option<u32>,
+    held: bool,
+    started: u64,
+    gain: f32,
+    pan: f32,
+}
+
+/// Fixed set of voices shared between notes, mixed down to stereo.
+///
+/// A note takes a silent voice if there is one, otherwise it steals the oldest released voice,
+/// and failing that the oldest voice still held.
+pub struct VoiceAllocator<V: Voice> {
+    slots: Vec<Slot<V>>,
+    clock: u64,
+}
+
+impl<V: Voice> VoiceAllocator<V> {
+    pub fn new(voices: Vec<V>) -> VoiceAllocator<V> {
+        let slots = voices.into_iter().map(|voice| {
+            Slot { voice, note: None, held: false, started: 0, gain: 1.0, pan: 0.0 }
+        }).collect();
+        VoiceAllocator { slots, clock: 0 }
+    }
+
+    /// Start note, identified by number for the later note_off, with its own gain and pan.
+    pub fn note_on(&mut self, note: u32, frequency: f64, velocity: f32, gain: f32, pan: f32) {
+        let chosen = {
+            let free = self.slots.iter().position(|s| !s.voice.is_active());
+            let oldest = |held: bool| {
+                self.slots.iter().enumerate().filter(|&(_, s)| s.held == held)
+                          .min_by_key(|&(_, s)| s.started).map(|(i, _)| i)
+            };
+            free.or_else(|| oldest(false)).or_else(|| oldest(true))
+        };
+        if let Some(i) = chosen {
+            let slot = &mut self.slots[i];
+            slot.voice.note_on(frequency, velocity);
+            slot.note = Some(note);
+            slot.held = true;
+            slot.started = self.clock;
+            slot.gain = gain;
+            slot.pan = pan;
+        }
+    }
+
+    /// Release every voice playing note.
+    pub fn note_off(&mut self, note: u32) {
+        for slot in self.slots.iter_mut().filter(|s| s.held && s.note == Some(note)) {
+            slot.voice.note_off();
+            slot.held = false;
+        }
+    }
+
+    /// Retune every voice playing note.
+    pub fn set_frequency(&mut self, note: u32, frequency: f64) {
+        for slot in self.slots.iter_mut().filter(|s| s.note == Some(note)) {
+            slot.voice.set_frequency(frequency);
+        }
+    }
+
+    /// Mix the next sample of all active voices.
+    pub fn tick(&mut self) -> (f32, f32) {
+        self.clock += 1;
+        let (mut left, mut right) = (0.0f32, 0.0f32);
+        for slot in self.slots.iter_mut().filter(|s| s.voice.is_active()) {
+            let out = slot.voice.tick() * slot.gain;
+            let (l, r) = pan_gains(slot.pan);
+            left += out * l;
+            right += out * r;
+        }
+        (left, right)
+    }
+}
+
+/// A note to be played, times in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteEvent {
+    pub time: f64,
+    pub duration: f64,
+    pub frequency: f64,
+    pub velocity: f32,
+    pub gain: f32,
+    pub pan: f32,
+}
+
+/// Play notes through the allocator for run_length seconds, returning the left and right channels.
+pub fn render_notes<V: Voice>(allocator: &mut VoiceAllocator<V>, notes: &[NoteEvent], run_length: f64,
+                              sample_rate: f64) -> (Vec<f32>, Vec<f32>) {
+    let num_samples: usize = (run_length * sample_rate).round() as usize;
+    let to_samples = |t: f64| (t * sample_rate).round() as usize;
+    // Starts and ends by sample, the note number being its index.
+    let mut changes: Vec<(usize, bool, u32)> = Vec::with_capacity(notes.len() * 2);
+    for (i, n) in notes.iter().enumerate() {
+        let start = to_samples(n.time);
+        // Held for at least a sample, an off on the start sample would sort first and never end
+        // the note. Hits with on and off together still sound through their release.
+        let end = to_samples(n.time + n.duration).max(start + 1);
+        changes.push((start, true, i as u32));
+        changes.push((end, false, i as u32));
+    }
+    // Note offs sort first, freeing voices for notes starting on the same sample.
+    changes.sort();
+
+    let mut left: Vec<f32> = Vec::with_capacity(num_samples);
+    let mut right: Vec<f32> = Vec::with_capacity(num_samples);
+    let mut next = 0;
+    for i in 0..num_samples {
+        while next < changes.len() && changes[next].0 <= i {
+            let (_, on, note) = changes[next];
+            if on {
+                let n = &notes[note as usize];
+                allocator.note_on(note, n.frequency, n.velocity, n.gain, n.pan);
+            } else {
+                allocator.note_off(note);
+            }
+            next += 1;
+        }
+        let (l, r) = allocator.tick();
+        left.push(l);
+        right.push(r);
+    }
+
+    (left, right)
+}
+
+/// Notes of a chord played low to high, strum seconds apart, fanned out across the stereo
+/// field by spread, 0.0 to 1.0.
+pub fn strum(frequencies: &[f64], velocities: &[f32], strum: f64, spread: f32, duration: f64) -> Vec<NoteEvent> {
+    let count = frequencies.len();
+    // Share the headroom between the notes so the chord can't clip.
+    let gain = 1.0 / count.max(1) as f32;
+    frequencies.iter().zip(velocities.iter()).enumerate().map(|(i, (f, v))| {
+        let place = if count > 1 { 2.0 * i as f32 / (count - 1) as f32 - 1.0 } else { 0.0 };
+        let time = strum * i as f64;
+        NoteEvent { time, duration: (duration - time).max(0.0), frequency: *f, velocity: *v, gain,
+                    pan: place * spread }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steals_the_oldest_voice() {
+        let voices = (0..2).map(|_| SineVoice::new(1000.0)).collect();
+        let mut alloc = VoiceAllocator::new(voices);
+        alloc.note_on(1, 100.0, 1.0, 1.0, 0.0);
+        alloc.tick();
+        alloc.note_on(2, 200.0, 1.0, 1.0, 0.0);
+        alloc.tick();
+        alloc.note_on(3, 300.0, 1.0, 1.0, 0.0);
+        let notes: Vec<Option<u32>> = alloc.slots.iter().map(|s| s.note).collect();
+        assert_eq!(notes, vec![Some(3), Some(2)]);
+
+        // Released voices go before held ones.
+        alloc.tick();
+        alloc.note_off(2);
+        alloc.note_on(4, 400.0, 1.0, 1.0, 0.0);
+        let notes: Vec<Option<u32>> = alloc.slots.iter().map(|s| s.note).collect();
+        assert_eq!(notes, vec![Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn hard_panned_notes() {
+        let voices = (0..2).map(|_| SineVoice::new(1000.0)).collect();
+        let mut alloc = VoiceAllocator::new(voices);
+        let notes = strum(&[100.0, 150.0], &[1.0, 1.0], 0.0, 1.0, 0.5);
+        let (left, right) = render_notes(&mut alloc, &notes, 1.0, 1000.0);
+        assert_eq!(left.len(), 1000);
+        // Each string on its own side, and both silent after the release.
+        assert!(left[100..400].iter().zip(right[100..400].iter()).any(|(l, r)| (l - r).abs() > 0.1));
+        assert!(left[600..].iter().chain(right[600..].iter()).all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn notes_shorter_than_a_sample_sound_and_end() {
+        let voices = vec![SineVoice::new(1000.0)];
+        let mut alloc = VoiceAllocator::new(voices);
+        let mut notes = strum(&[100.0, 150.0], &[1.0, 1.0], 0.0, 0.0, 0.5);
+        notes[0].duration = 0.0;
+        notes[1].time = 0.5;
+        notes[1].duration = 0.0004;
+        let (left, _) = render_notes(&mut alloc, &notes, 1.0, 1000.0);
+        // Each is heard, then released well before the next.
+        for start in [0, 500].iter() {
+            assert!(left[*start..*start + 50].iter().any(|s| s.abs() > 0.01));
+            assert!(left[*start + 400..*start + 500].iter().all(|s| *s == 0.0));
+        }
+    }
+}
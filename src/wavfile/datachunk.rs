@@ -15,12 +15,14 @@ pub struct DataChunk<T> {
     data_header: [u8; 4], // "data"
     size_data: u32,
     sample_vector: Vec<T>,
+    channels: u16,
     read_cur: usize,
 }
 
 impl Read for DataChunk<F32Sample> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let out_size: usize = self.size_data as usize + 4;
+        // Chunk header of "data" and the size, then the samples.
+        let out_size: usize = self.size_data as usize + 8;
         // Temporary buffer for transmuting F32Sample, and u32 header size component.
         let mut tmb: [u8; 4] = [0; 4];
         let mut off: usize = 0;
@@ -46,8 +48,10 @@ impl Read for DataChunk<F32Sample> {
                 off = off + append_bytes(&tmb, buf, off);
             }
 
-            // Split the Vec<F32Sample> of data at the read_cur position. 
-            let (_, work_slice) = self.sample_vector.split_at( self.read_cur / 4 );
+            // Split the Vec<F32Sample> of data at the read_cur position, which counts the 8 header
+            // bytes as well as the samples already sent.
+            let sent = self.read_cur.saturating_sub(8) / 4;
+            let (_, work_slice) = self.sample_vector.split_at( sent.min(self.sample_vector.len()) );
 
             // Loop over that slice.
             for fl in work_slice {
@@ -56,7 +60,7 @@ impl Read for DataChunk<F32Sample> {
                 do_transmute!(f32_to_u8, *fl, &mut tmb, &mut x, 4);
                 // Check buf position before write, if full return Ok(off) for the bytes written.
                 // increment read_cur for next call from copy(). 
-                if (off + 4) < buf.len() {
+                if (off + 4) <= buf.len() {
                     off = off + append_bytes(&tmb, buf, off);
                 } else {
                     self.read_cur = self.read_cur + off;
@@ -87,6 +91,10 @@ impl DataChunk<F32Sample> {
     pub fn len(&self) -> usize {
         self.sample_vector.len()
     }
+    /// Number of interleaved channels in the samples.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
 }
 
 impl Default for DataChunk<F32Sample> {
@@ -98,6 +106,7 @@ impl Default for DataChunk<F32Sample> {
             data_header: [b'd', b'a', b't', b'a'],
             size_data: 0,
             sample_vector: Vec::new(),
+            channels: 1,
             read_cur: 0,
         }
     }
@@ -135,10 +144,12 @@ pub fn create_stereo_datachunk(one: Vec<F32Sample>, two: Vec<F32Sample>) -> Data
     }
     let mut len: u32;
     {
+        // len counts the samples of both channels.
         len = dc.len() as u32;
-        len = len * 8;
+        len = len * 4;
     }
     dc.set_size(len);
+    dc.channels = 2;
     dc
 }
 
@@ -12,6 +12,12 @@ mod datachunk;
 pub use datachunk::DataChunk;
 pub use datachunk::{create_mono_datachunk, create_stereo_datachunk};
 
+mod reader;
+pub use reader::{WavSamples, read_wav};
+
+mod writer;
+pub use writer::WavWriter;
+
 /// Struct representing an overall .wav file with a single data chunk.
 ///
 /// Artifact of thinking about packing the component structs then using unsafe mem operations to
@@ -33,7 +39,7 @@ impl Wav<F32Sample> {
     }
 }
 
-/// Function to package provided Datachunk as a mono .wav struct.
+/// Function to package provided Datachunk as a .wav struct, mono or stereo to match the data.
 ///
 /// Current support functions only provide 32bit sample size.
 pub fn create_wav(data_in: DataChunk<F32Sample>, sample_rate: u32, sample_bits: u32) -> Wav<F32Sample> {
@@ -48,7 +54,8 @@ pub fn create_wav(data_in: DataChunk<F32Sample>, sample_rate: u32, sample_bits:
     fmt.set_block_align( (num_channels * (sample_bits/8)) as u16 );
     fmt.set_bits_sample( sample_bits as u16 );
 */
-    let fmt = set_fmt(sample_rate, sample_bits, 1);
+    let mut fmt = set_fmt(sample_rate, sample_bits, data_in.channels() as u32);
+    fmt.set_number_channels(data_in.channels());
     let data_size: u32 = (data_in.len() as u32) * (sample_bits / 8);
     let total_size: u32 = data_size + format_chunk_size + data_chunk_header_size;
 
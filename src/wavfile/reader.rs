@@ -0,0 +1,151 @@
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::io::Result;
+
+use super::F32Sample;
+
+// Format tags from the fmt chunk.
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Samples read back from a .wav file, interleaved when there is more than one channel.
+#[derive(Debug)]
+pub struct WavSamples {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<F32Sample>,
+}
+
+impl WavSamples {
+    /// Average the channels down to a single one.
+    pub fn to_mono(&self) -> Vec<F32Sample> {
+        let chans = self.channels.max(1) as usize;
+        self.samples.chunks(chans)
+            .map(|frame| frame.iter().sum::<F32Sample>() / chans as F32Sample)
+            .collect()
+    }
+}
+
+fn bad_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Decode one sample to the range -1.0 to 1.0.
+fn decode(bytes: &[u8], format: u16, bits: u16) -> Result<F32Sample> {
+    let s = match (format, bits) {
+        (FORMAT_PCM, 8) => (bytes[0] as F32Sample - 128.0) / 128.0,
+        (FORMAT_PCM, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as F32Sample / 32768.0,
+        (FORMAT_PCM, 24) => {
+            // Shift into the top of an i32 to sign extend.
+            let v = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            (v >> 8) as F32Sample / 8_388_608.0
+        }
+        // This library writes float data with the PCM tag, so 32 bit PCM is read as float too.
+        (FORMAT_PCM, 32) | (FORMAT_FLOAT, 32) => {
+            f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        }
+        (FORMAT_FLOAT, 64) => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[..8]);
+            f64::from_le_bytes(b) as F32Sample
+        }
+        _ => return Err(bad_data("Unsupported sample format.")),
+    };
+    Ok(s)
+}
+
+/// Read a whole .wav file into floating point samples.
+///
+/// Handles 8, 16, 24 and 32 bit PCM and 32 or 64 bit float. Chunk sizes running past the end
+/// of the file are cut short rather than treated as an error.
+pub fn read_wav<R: Read>(input: &mut R) -> Result<WavSamples> {
+    let mut bytes: Vec<u8> = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(bad_data("Not a RIFF WAVE file."));
+    }
+
+    // Format tag, channels, sample rate and bits per sample.
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = u32_at(&bytes, pos + 4) as usize;
+        let body = pos + 8;
+        let end = (body + size).min(bytes.len());
+
+        if id == b"fmt " {
+            if end - body < 16 {
+                return Err(bad_data("Short fmt chunk."));
+            }
+            let mut tag = u16_at(&bytes, body);
+            if tag == FORMAT_EXTENSIBLE && end - body >= 26 {
+                // First two bytes of the sub format GUID are the real tag.
+                tag = u16_at(&bytes, body + 24);
+            }
+            format = Some((tag, u16_at(&bytes, body + 2), u32_at(&bytes, body + 4),
+                           u16_at(&bytes, body + 14)));
+        } else if id == b"data" {
+            let (tag, channels, sample_rate, bits) = format.ok_or_else(|| bad_data("data before fmt chunk."))?;
+            let width = (bits as usize).div_ceil(8);
+            if width == 0 || channels == 0 {
+                return Err(bad_data("Bad fmt chunk."));
+            }
+            let mut samples: Vec<F32Sample> = Vec::with_capacity((end - body) / width);
+            for s in bytes[body..end].chunks(width).filter(|s| s.len() == width) {
+                samples.push(decode(s, tag, bits)?);
+            }
+            return Ok(WavSamples { sample_rate, channels, samples });
+        }
+        // Chunks are padded to an even length.
+        pos = body + size + (size & 1);
+    }
+
+    Err(bad_data("No data chunk."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::copy;
+    use super::super::{create_wav, create_mono_datachunk, create_stereo_datachunk};
+
+    #[test]
+    fn reads_back_written_wav() {
+        // Long enough to take several reads through copy().
+        let payload: Vec<f32> = (0..50000).map(|i| i as f32 / 50000.0).collect();
+        let mut wav = create_wav(create_mono_datachunk(payload.clone()), 22050, 32);
+        let mut file: Vec<u8> = Vec::new();
+        copy(&mut wav.header, &mut file).unwrap();
+        copy(&mut wav.format_chunk, &mut file).unwrap();
+        copy(&mut wav.data, &mut file).unwrap();
+
+        let read = read_wav(&mut &file[..]).unwrap();
+        assert_eq!(read.sample_rate, 22050);
+        assert_eq!(read.channels, 1);
+        assert_eq!(read.samples, payload);
+    }
+
+    #[test]
+    fn reads_back_stereo() {
+        let mut wav = create_wav(create_stereo_datachunk(vec![0.5, 0.25], vec![-0.5, -0.25]), 44100, 32);
+        let mut file: Vec<u8> = Vec::new();
+        copy(&mut wav.header, &mut file).unwrap();
+        copy(&mut wav.format_chunk, &mut file).unwrap();
+        copy(&mut wav.data, &mut file).unwrap();
+
+        let read = read_wav(&mut &file[..]).unwrap();
+        assert_eq!(read.channels, 2);
+        assert_eq!(read.samples, vec![0.5, -0.5, 0.25, -0.25]);
+        assert_eq!(read.to_mono(), vec![0.0, 0.0]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::prelude::*;
+use std::io::{copy, Error, ErrorKind, Result, SeekFrom};
+
+use super::{F32Sample, WavHeader, set_fmt};
+
+// Offsets of the RIFF size and of the data chunk size in the file.
+const RIFF_SIZE_POS: u64 = 4;
+const DATA_SIZE_POS: u64 = 40;
+
+/// Writer for a .wav file of 32bit samples given a block at a time.
+///
+/// The file is laid out as create_wav() does it, the header sizes being filled in by finish()
+/// once the length is known. Memory use doesn't grow with the length of the file.
+pub struct WavWriter<W: Write + Seek> {
+    output: W,
+    channels: u16,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the header for a file of interleaved channels, ready for samples.
+    pub fn new(mut output: W, channels: u16, sample_rate: u32) -> Result<WavWriter<W>> {
+        let mut hdr: WavHeader = Default::default();
+        let mut fmt = set_fmt(sample_rate, 32, channels as u32);
+        fmt.set_number_channels(channels);
+        copy(&mut hdr, &mut output)?;
+        copy(&mut fmt, &mut output)?;
+        // Chunk size is zero until finish().
+        output.write_all(b"data")?;
+        output.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { output, channels, data_size: 0 })
+    }
+
+    /// Append interleaved samples, a whole number of frames.
+    pub fn write_samples(&mut self, samples: &[F32Sample]) -> Result<()> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(samples.len() * 4);
+        for s in samples {
+            bytes.extend_from_slice(&s.to_le_bytes());
+        }
+        let size = (self.data_size as usize).checked_add(bytes.len()).filter(|s| *s <= u32::MAX as usize - 32)
+                       .ok_or_else(|| Error::new(ErrorKind::Other, "Data too long for a .wav."))?;
+        self.output.write_all(&bytes)?;
+        self.data_size = size as u32;
+        Ok(())
+    }
+
+    /// Append a block of each channel, interleaving them. The blocks must be the same length.
+    pub fn write_channels(&mut self, blocks: &[&[F32Sample]]) -> Result<()> {
+        if blocks.len() != self.channels as usize || blocks.iter().any(|b| b.len() != blocks[0].len()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Need one block of each channel, all the same length."));
+        }
+        let frames = blocks.first().map_or(0, |b| b.len());
+        let interleaved: Vec<F32Sample> = (0..frames).flat_map(|i| blocks.iter().map(move |b| b[i])).collect();
+        self.write_samples(&interleaved)
+    }
+
+    /// Fill in the sizes in the header and hand back the output.
+    pub fn finish(mut self) -> Result<W> {
+        // Same RIFF size as create_wav() gives.
+        let total_size = self.data_size + 24 + 8;
+        self.output.seek(SeekFrom::Start(RIFF_SIZE_POS))?;
+        self.output.write_all(&total_size.to_le_bytes())?;
+        self.output.seek(SeekFrom::Start(DATA_SIZE_POS))?;
+        self.output.write_all(&self.data_size.to_le_bytes())?;
+        self.output.seek(SeekFrom::End(0))?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use super::super::{create_wav, create_stereo_datachunk};
+
+    #[test]
+    fn streams_the_same_file_as_create_wav() {
+        let left: Vec<f32> = (0..50000).map(|i| i as f32 / 50000.0).collect();
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+
+        let mut wav = create_wav(create_stereo_datachunk(left.clone(), right.clone()), 44100, 32);
+        let mut expected: Vec<u8> = Vec::new();
+        copy(&mut wav.header, &mut expected).unwrap();
+        copy(&mut wav.format_chunk, &mut expected).unwrap();
+        copy(&mut wav.data, &mut expected).unwrap();
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 2, 44100).unwrap();
+        for (l, r) in left.chunks(4096).zip(right.chunks(4096)) {
+            writer.write_channels(&[l, r]).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap().into_inner(), expected);
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 2, 44100).unwrap();
+        assert!(writer.write_channels(&[&left[..10], &right[..9]]).is_err());
+    }
+}